@@ -1,25 +1,200 @@
 // Copyright 2020 IOTA Stiftung
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::print_error;
+use crate::{
+    batch, bech32_hrp, config::Config, dedup_nodes, output, parse_address, print_error, show_notification,
+    signer_type_name, simulate_latency, units,
+};
 
 use anyhow::Result;
+use bech32::FromBase32;
+use chrono::{Local, Utc};
 use clap::{App, ArgMatches};
-use dialoguer::Input;
+use dialoguer::{console::Term, Confirm, Input};
+use indicatif::ProgressBar;
+use iota_client::{bee_rest_api::types::dtos::LedgerInclusionStateDto, Client};
 use iota_wallet::{
     account::AccountHandle,
-    address::Address,
+    address::{Address, AddressOutput, AddressWrapper},
     client::ClientOptionsBuilder,
-    message::{Message, MessageId, MessagePayload, MessageType, TransactionEssence, Transfer},
+    event::{
+        on_confirmation_state_change, on_new_transaction, remove_confirmation_state_change_listener,
+        remove_new_transaction_listener,
+    },
+    message::{
+        IndexationPayload, Message, MessageId, MessagePayload, MessageType, RemainderValueStrategy, TransactionEssence,
+        TransactionInput, TransactionOutput, Transfer, TransferOutput,
+    },
 };
+use qrcode::{render::unicode::Dense1x2, QrCode};
 
-use std::{num::NonZeroU64, process::Command, str::FromStr};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    future::Future,
+    num::NonZeroU64,
+    process::Command,
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
 
-fn print_message(message: &Message) {
-    println!("MESSAGE {}", message.id());
+// the faucet enqueue endpoint of each network, by network id: the bech32 prefix can't tell the networks apart since
+// testnet and devnet both use `atoi`
+const FAUCET_URLS: &[(&str, &str)] = &[(
+    "chrysalis-devnet",
+    "https://faucet.chrysalis-devnet.iota.cafe/api/plugins/faucet/enqueue",
+)];
+const MAINNET_HRP: &str = "iota";
+// the BIP-44 coin type used by the wallet library to derive addresses
+const IOTA_COIN_TYPE: u32 = 4218;
+// the protocol allows 127 outputs per transaction, one is kept for the remainder
+const MAX_TRANSFER_OUTPUTS: usize = 126;
+// the price API used by `balance --fiat` unless the config sets another one, `{currency}` is replaced by the code
+const DEFAULT_PRICE_API: &str = "https://api.coingecko.com/api/v3/simple/price?ids=iota&vs_currencies={currency}";
+const PRICE_CACHE_DURATION: Duration = Duration::from_secs(60);
+// the last fetched price, with its currency and when it was fetched
+static PRICE_CACHE: Mutex<Option<(String, Instant, f64)>> = Mutex::new(None);
+// the protocol limit of an indexation payload index
+const MAX_INDEX_LENGTH: usize = 64;
+// the most addresses `address --count` generates at once
+const MAX_GENERATED_ADDRESSES: usize = 100;
+// commands that send requests to the account nodes
+const NETWORK_COMMANDS: &[&str] = &[
+    "sync",
+    "rebroadcast",
+    "refresh-message",
+    "transfer",
+    "send-from-file",
+    "promote",
+    "retry",
+    "reattach",
+    "poll-deposits",
+    "consolidate",
+    "triage",
+    "reattach-all",
+    "faucet",
+    "node-info",
+];
+
+// gets the value of a transaction message, if any
+pub fn message_value(message: &Message) -> Option<u64> {
+    if let Some(MessagePayload::Transaction(tx)) = message.payload() {
+        let TransactionEssence::Regular(essence) = tx.essence();
+        Some(essence.value())
+    } else {
+        None
+    }
+}
+
+// displays bytes as UTF-8 if possible, or as hex otherwise
+fn display_bytes(bytes: &[u8]) -> String {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_string(),
+        Err(_) => format!("0x{}", hex::encode(bytes)),
+    }
+}
+
+// gets the value of a transaction message, negative if the account sent it
+fn message_signed_value(message: &Message) -> Option<i64> {
+    if let Some(MessagePayload::Transaction(tx)) = message.payload() {
+        let TransactionEssence::Regular(essence) = tx.essence();
+        let value = essence.value() as i64;
+        Some(if essence.incoming() { value } else { -value })
+    } else {
+        None
+    }
+}
+
+// gets the value exchanged with an address in a transaction message, negative if the account paid it
+fn counterparty_value(message: &Message, address: &str) -> Option<i64> {
     if let Some(MessagePayload::Transaction(tx)) = message.payload() {
         let TransactionEssence::Regular(essence) = tx.essence();
-        println!("--- Value: {:?}", essence.value());
+        if essence.incoming() {
+            let sent_by_counterparty = essence.inputs().iter().any(|input| match input {
+                TransactionInput::Utxo(input) => input
+                    .metadata()
+                    .as_ref()
+                    .map_or(false, |output| output.address().to_bech32() == address),
+                _ => false,
+            });
+            if sent_by_counterparty {
+                return Some(essence.value() as i64);
+            }
+        } else {
+            let paid: u64 = essence
+                .outputs()
+                .iter()
+                .filter_map(|output| match output {
+                    TransactionOutput::SignatureLockedSingle(output) if output.address().to_bech32() == address => {
+                        Some(*output.amount())
+                    }
+                    _ => None,
+                })
+                .sum();
+            if paid > 0 {
+                return Some(-(paid as i64));
+            }
+        }
+    }
+    None
+}
+
+// the structured form of a message for `--json` output
+fn message_json(message: &Message) -> serde_json::Value {
+    let payload_type = match message.payload() {
+        Some(MessagePayload::Transaction(_)) => Some("transaction"),
+        Some(MessagePayload::Indexation(_)) => Some("indexation"),
+        Some(MessagePayload::Milestone(_)) => Some("milestone"),
+        _ => None,
+    };
+    serde_json::json!({
+        "id": message.id().to_string(),
+        "timestamp": message.timestamp().to_rfc3339(),
+        "value": message_value(message),
+        "broadcasted": message.broadcasted(),
+        "confirmed": message.confirmed(),
+        "type": payload_type,
+    })
+}
+
+pub fn print_message(message: &Message) {
+    println!("MESSAGE {}", message.id());
+    if let Some(value) = message_value(message) {
+        println!("--- Value: {:?}", value);
+    }
+    match message.payload() {
+        Some(MessagePayload::Indexation(indexation)) => {
+            println!("--- Index: {}", display_bytes(indexation.index()));
+            println!("--- Data: {}", display_bytes(indexation.data()));
+        }
+        Some(MessagePayload::Milestone(_)) => println!("--- Milestone"),
+        Some(MessagePayload::Transaction(tx)) => {
+            let TransactionEssence::Regular(essence) = tx.essence();
+            println!("--- Inputs: {}", essence.inputs().len());
+            for output in essence.outputs() {
+                match output {
+                    TransactionOutput::SignatureLockedSingle(output) => println!(
+                        "--- Output: {} to {}{}",
+                        output.amount(),
+                        output.address().to_bech32(),
+                        if *output.remainder() { " (remainder)" } else { "" }
+                    ),
+                    TransactionOutput::SignatureLockedDustAllowance(output) => println!(
+                        "--- Output: {} to {} (dust allowance)",
+                        output.amount(),
+                        output.address().to_bech32()
+                    ),
+                    _ => println!("--- Output: unsupported kind"),
+                }
+            }
+            // the indexation attached with `transfer --tag`
+            if let Some(MessagePayload::Indexation(indexation)) = essence.payload() {
+                println!("--- Index: {}", display_bytes(indexation.index()));
+                println!("--- Data: {}", display_bytes(indexation.data()));
+            }
+        }
+        _ => {}
     }
     println!("--- Timestamp: {:?}", message.timestamp());
     println!(
@@ -32,20 +207,121 @@ fn print_message(message: &Message) {
     );
 }
 
+// prints the parents of a message along with their confirmation state on the node
+async fn print_message_parents(account_handle: &AccountHandle, message: &Message) -> Result<()> {
+    let client = get_client(account_handle).await?;
+    for parent in message.parents() {
+        let state = match client.get_message().metadata(parent).await {
+            Ok(metadata) => match metadata.referenced_by_milestone_index {
+                Some(index) => format!("referenced by milestone {}", index),
+                None => "not referenced".to_string(),
+            },
+            Err(_) => "unknown".to_string(),
+        };
+        println!("--- Parent: {} ({})", parent, state);
+    }
+    Ok(())
+}
+
 async fn print_address(account_handle: &AccountHandle, address: &Address) {
     println!("ADDRESS {:?}", address.address().to_bech32());
-    println!("Total balance: {}", address.balance());
+    println!("Total balance: {}", units::format_amount(address.balance()));
     println!(
         "--- Balance: {}",
-        account_handle
-            .read()
-            .await
-            .address_available_balance(address)
-            .await
-            .unwrap()
+        units::format_amount(
+            account_handle
+                .read()
+                .await
+                .address_available_balance(address)
+                .await
+                .unwrap()
+        )
     );
     println!("--- Index: {}", address.key_index());
     println!("--- Change address: {}", address.internal());
+    println!(
+        "--- Derivation path: m/44'/{}'/{}'/{}'/{}'",
+        IOTA_COIN_TYPE,
+        account_handle.index().await,
+        *address.internal() as u8,
+        address.key_index()
+    );
+}
+
+// renders the data as a QR code made of unicode blocks, two modules per character
+fn print_qr_code(data: &str) -> Result<()> {
+    let code = QrCode::new(data.as_bytes()).map_err(|e| anyhow::anyhow!("Failed to build QR code: {}", e))?;
+    println!("{}", code.render::<Dense1x2>().quiet_zone(true).build());
+    Ok(())
+}
+
+// prints the number of messages and the net value of each day
+fn print_messages_by_day(messages: &[Message], expand: bool) {
+    let mut days: BTreeMap<String, Vec<&Message>> = BTreeMap::new();
+    for message in messages {
+        days.entry(message.timestamp().format("%Y-%m-%d").to_string())
+            .or_default()
+            .push(message);
+    }
+    for (day, messages) in days {
+        let net_value: i64 = messages.iter().filter_map(|m| message_signed_value(m)).sum();
+        println!("{}: {} messages, net value {:+}", day, messages.len(), net_value);
+        if expand {
+            messages.iter().for_each(|m| print_message(m));
+        }
+    }
+}
+
+// shows a spinner while the future runs, or just logs the message when stdout isn't a terminal; progress messages are
+// hidden with `--quiet`, the spinner included
+async fn with_spinner<F: Future>(message: &'static str, future: F) -> F::Output {
+    if !log::log_enabled!(log::Level::Info) {
+        return future.await;
+    }
+    if !Term::stdout().is_term() {
+        log::info!("{}", message);
+        return future.await;
+    }
+    let spinner = ProgressBar::new_spinner();
+    spinner.set_message(message);
+    spinner.enable_steady_tick(100);
+    let output = future.await;
+    spinner.finish_and_clear();
+    output
+}
+
+// explains node errors, which are opaque to new users, and keeps the others as they are
+fn node_error(error: iota_wallet::Error) -> anyhow::Error {
+    match error {
+        iota_wallet::Error::ClientError(e) => {
+            anyhow::anyhow!(
+                "Could not reach the account node ({}), check its URL with `set-node`",
+                e
+            )
+        }
+        error => error.into(),
+    }
+}
+
+// builds a client connected to the account nodes
+async fn get_client(account_handle: &AccountHandle) -> Result<Client> {
+    let client_options = account_handle.client_options().await;
+    let nodes: Vec<&str> = client_options
+        .primary_node()
+        .iter()
+        .chain(client_options.nodes())
+        .map(|node| node.url.as_str())
+        .collect();
+    get_nodes_client(&nodes).await
+}
+
+// builds a client connected to the given nodes
+async fn get_nodes_client(nodes: &[&str]) -> Result<Client> {
+    let mut builder = Client::builder().with_node_sync_disabled();
+    for node in nodes {
+        builder = builder.with_node(node)?;
+    }
+    Ok(builder.finish().await?)
 }
 
 // `list-messages` command
@@ -55,7 +331,13 @@ async fn list_messages_command(account_handle: &AccountHandle, matches: &ArgMatc
             if let Ok(message_id) = MessageId::from_str(id) {
                 let account = account_handle.read().await;
                 if let Some(message) = account.get_message(&message_id).await {
+                    if matches.is_present("json") || output::has_output_file() {
+                        return output::write_json(&message_json(&message));
+                    }
                     print_message(&message);
+                    if matches.is_present("parents") {
+                        print_message_parents(account_handle, &message).await?;
+                    }
                 } else {
                     println!("Message not found");
                 }
@@ -71,99 +353,735 @@ async fn list_messages_command(account_handle: &AccountHandle, matches: &ArgMatc
                     "failed" => Some(MessageType::Failed),
                     "unconfirmed" => Some(MessageType::Unconfirmed),
                     "value" => Some(MessageType::Value),
-                    _ => panic!("unexpected message type"),
+                    _ => {
+                        return Err(anyhow::anyhow!(
+                            "Message type must be one of received, sent, failed, unconfirmed or value"
+                        ))
+                    }
+                }
+            } else {
+                None
+            };
+            // 0 lists every message, as the wallet library does
+            let count = match matches.value_of("count") {
+                Some(count) => count
+                    .parse::<usize>()
+                    .map_err(|_| anyhow::anyhow!("Count must be a number"))?,
+                None => 0,
+            };
+            let skip = match matches.value_of("skip") {
+                Some(skip) => skip
+                    .parse::<usize>()
+                    .map_err(|_| anyhow::anyhow!("Skip must be a number"))?,
+                None => 0,
+            };
+            let mut messages = account.list_messages(count, skip, message_type).await?;
+            let counterparty = match matches.value_of("counterparty") {
+                Some(address) => {
+                    let address = normalize_address(address)?;
+                    messages.retain(|m| counterparty_value(m, &address).is_some());
+                    Some(address)
                 }
+                None => None,
+            };
+            let hidden = if matches.is_present("confirmed-only") {
+                let fetched = messages.len();
+                messages.retain(|m| *m.confirmed() == Some(true));
+                Some(fetched - messages.len())
             } else {
                 None
             };
-            let messages = account.list_messages(0, 0, message_type).await?;
+            // every stored message has a timestamp, set when the wallet first saw it
+            match matches.value_of("sort") {
+                Some("oldest") => messages.sort_by(|a, b| a.timestamp().cmp(b.timestamp())),
+                _ => messages.sort_by(|a, b| b.timestamp().cmp(a.timestamp())),
+            }
+            if matches.is_present("json") || output::has_output_file() {
+                let messages: Vec<_> = messages.iter().map(message_json).collect();
+                return output::write_json(&serde_json::json!(messages));
+            }
             if messages.is_empty() {
                 println!("No messages found");
+            } else if matches.is_present("group-by-day") {
+                print_messages_by_day(&messages, matches.is_present("expand"));
             } else {
                 messages.iter().for_each(|m| print_message(m));
             }
+            if let Some(address) = counterparty {
+                let net_value: i64 = messages.iter().filter_map(|m| counterparty_value(m, &address)).sum();
+                println!(
+                    "{} messages with {}, net value {:+}",
+                    messages.len(),
+                    address,
+                    net_value
+                );
+            }
+            if let Some(hidden) = hidden {
+                println!("{} unconfirmed messages hidden", hidden);
+            }
+        }
+    }
+    Ok(())
+}
+
+// `balance-history` command
+async fn balance_history_command(account_handle: &AccountHandle, matches: &ArgMatches) -> Result<()> {
+    if let Some(matches) = matches.subcommand_matches("balance-history") {
+        let mut messages = account_handle.read().await.list_messages(0, 0, None).await?;
+        messages.retain(|m| *m.confirmed() == Some(true));
+        messages.sort_by(|a, b| a.timestamp().cmp(b.timestamp()));
+        // the running balance after each confirmed transaction
+        let mut balance = 0i64;
+        let mut timeline = Vec::new();
+        for message in &messages {
+            if let Some(value) = message_signed_value(message) {
+                balance += value;
+                timeline.push((message, value, balance));
+            }
+        }
+
+        if matches.is_present("json") || output::has_output_file() {
+            let timeline: Vec<_> = timeline
+                .iter()
+                .map(|(message, value, balance)| {
+                    serde_json::json!({
+                        "timestamp": message.timestamp().to_rfc3339(),
+                        "message_id": message.id().to_string(),
+                        "change": value,
+                        "balance": balance,
+                    })
+                })
+                .collect();
+            output::write_json(&serde_json::json!(timeline))?;
+        } else if matches.is_present("csv") {
+            println!("timestamp,message_id,change,balance");
+            for (message, value, balance) in &timeline {
+                println!(
+                    "{},{},{},{}",
+                    message.timestamp().to_rfc3339(),
+                    message.id(),
+                    value,
+                    balance
+                );
+            }
+        } else if timeline.is_empty() {
+            println!("No confirmed transactions found");
+        } else {
+            for (message, value, balance) in &timeline {
+                println!(
+                    "{} {:>+20} {:>20} ({})",
+                    message.timestamp().with_timezone(&Local).format("%Y-%m-%d %H:%M:%S"),
+                    value,
+                    balance,
+                    message.id()
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+// `export-history` command
+async fn export_history_command(account_handle: &AccountHandle, matches: &ArgMatches) -> Result<()> {
+    if let Some(matches) = matches.subcommand_matches("export-history") {
+        let path = matches.value_of("path").unwrap();
+        let messages = account_handle.read().await.list_messages(0, 0, None).await?;
+        let mut csv = String::from("message_id,timestamp,value,direction,broadcasted,confirmed\n");
+        for message in &messages {
+            // messages without a transaction payload have no value or direction
+            let direction = match message.payload() {
+                Some(MessagePayload::Transaction(tx)) => {
+                    let TransactionEssence::Regular(essence) = tx.essence();
+                    if essence.incoming() {
+                        "received"
+                    } else {
+                        "sent"
+                    }
+                }
+                _ => "",
+            };
+            let confirmed = match message.confirmed() {
+                Some(confirmed) => confirmed.to_string(),
+                None => "pending".to_string(),
+            };
+            csv.push_str(&format!(
+                "{},{},{},{},{},{}\n",
+                message.id(),
+                message.timestamp().to_rfc3339(),
+                message_value(message)
+                    .map(|value| value.to_string())
+                    .unwrap_or_default(),
+                direction,
+                message.broadcasted(),
+                confirmed
+            ));
+        }
+        fs::write(path, csv).map_err(|e| anyhow::anyhow!("Failed to write {}: {}", path, e))?;
+        println!("{} messages exported to {}", messages.len(), path);
+    }
+    Ok(())
+}
+
+// validates a bech32 address given to a lookup command and lowercases it like the stored addresses, since bech32
+// addresses may also be written in uppercase
+fn normalize_address(bech32_address: &str) -> Result<String> {
+    let bech32_address = bech32_address.to_lowercase();
+    parse_address(&bech32_address)?;
+    Ok(bech32_address)
+}
+
+// `address-info` command
+async fn address_info_command(account_handle: &AccountHandle, matches: &ArgMatches) -> Result<()> {
+    if let Some(matches) = matches.subcommand_matches("address-info") {
+        let bech32_address = normalize_address(matches.value_of("address").unwrap())?;
+        let account = account_handle.read().await;
+        match account
+            .addresses()
+            .iter()
+            .find(|address| address.address().to_bech32() == bech32_address)
+        {
+            Some(address) => print_address(account_handle, address).await,
+            None => println!("Address {} doesn't belong to this account", bech32_address),
+        }
+    }
+    Ok(())
+}
+
+// `is-address-mine` command
+async fn is_address_mine_command(account_handle: &AccountHandle, matches: &ArgMatches) -> Result<()> {
+    if let Some(matches) = matches.subcommand_matches("is-address-mine") {
+        // a tampered address usually fails the checksum before it even gets compared
+        let bech32_address = normalize_address(matches.value_of("address").unwrap())?;
+        let account = account_handle.read().await;
+        match account
+            .addresses()
+            .iter()
+            .find(|address| address.address().to_bech32() == bech32_address)
+        {
+            Some(address) => println!(
+                "yes, index {} ({} address)",
+                address.key_index(),
+                if *address.internal() { "change" } else { "receive" }
+            ),
+            None => println!("no"),
         }
     }
     Ok(())
 }
 
+// `export-addresses` command
+async fn export_addresses_command(account_handle: &AccountHandle, matches: &ArgMatches) -> Result<()> {
+    if let Some(matches) = matches.subcommand_matches("export-addresses") {
+        let path = matches.value_of("path").unwrap();
+        let change = matches.value_of("change").unwrap_or("include");
+        let account = account_handle.read().await;
+        let addresses: Vec<String> = account
+            .addresses()
+            .iter()
+            .filter(|address| match change {
+                "exclude" => !*address.internal(),
+                "only" => *address.internal(),
+                _ => true,
+            })
+            .map(|address| address.address().to_bech32())
+            .collect();
+        let mut content = addresses.join("\n");
+        content.push('\n');
+        fs::write(path, content).map_err(|e| anyhow::anyhow!("Failed to write {}: {}", path, e))?;
+        println!("{} addresses exported to {}", addresses.len(), path);
+    }
+    Ok(())
+}
+
 // `list-addresses` command
-async fn list_addresses_command(account_handle: &AccountHandle, matches: &ArgMatches) {
-    if matches.subcommand_matches("list-addresses").is_some() {
+async fn list_addresses_command(account_handle: &AccountHandle, matches: &ArgMatches) -> Result<()> {
+    if let Some(matches) = matches.subcommand_matches("list-addresses") {
         let account = account_handle.read().await;
         let addresses = account.addresses();
+        let filtered = matches.is_present("with-balance") || matches.is_present("unused");
+        let listed: Vec<&Address> = addresses
+            .iter()
+            .filter(|address| {
+                if matches.is_present("with-balance") {
+                    address.balance() > 0
+                } else if matches.is_present("unused") {
+                    address.balance() == 0
+                } else {
+                    true
+                }
+            })
+            .collect();
+        if matches.is_present("json") || output::has_output_file() {
+            let mut entries = Vec::new();
+            for address in listed {
+                entries.push(serde_json::json!({
+                    "address": address.address().to_bech32(),
+                    "balance": address.balance(),
+                    "available_balance": account.address_available_balance(address).await?,
+                    "key_index": address.key_index(),
+                    "internal": address.internal(),
+                }));
+            }
+            return output::write_json(&serde_json::json!(entries));
+        }
         if addresses.is_empty() {
             println!("No addresses found");
         } else {
-            for address in addresses {
+            for address in &listed {
                 print_address(account_handle, address).await;
             }
+            if filtered {
+                println!("{} of {} addresses listed", listed.len(), addresses.len());
+            }
         }
     }
+    Ok(())
 }
 
 // `sync` command
-async fn sync_account_command(account_handle: &AccountHandle, matches: &ArgMatches) -> Result<()> {
+async fn sync_account_command(account_handle: &AccountHandle, matches: &ArgMatches, config: &mut Config) -> Result<()> {
     if let Some(matches) = matches.subcommand_matches("sync") {
-        let mut sync = account_handle.sync().await;
-        if let Some(gap_limit) = matches.value_of("gap") {
-            if let Ok(limit) = gap_limit.parse::<usize>() {
-                println!("Syncing with gap limit {}", limit);
-                sync = sync.gap_limit(limit);
-            } else {
-                return Err(anyhow::anyhow!("Gap limit must be a number"));
-            }
+        if let Some(window) = matches.value_of("resume-window") {
+            let window = window
+                .parse::<usize>()
+                .ok()
+                .filter(|window| *window > 0)
+                .ok_or_else(|| anyhow::anyhow!("Resume window must be a positive number"))?;
+            return sync_account_resumable(account_handle, window, config).await;
+        }
+        let interval = match matches.value_of("interval") {
+            Some(seconds) => Some(Duration::from_secs(
+                seconds
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|seconds| *seconds > 0)
+                    .ok_or_else(|| anyhow::anyhow!("Interval must be a positive number of seconds"))?,
+            )),
+            None => None,
+        };
+        match interval {
+            Some(interval) => loop {
+                log::info!("[{}] Syncing", Local::now().format("%Y-%m-%d %H:%M:%S"));
+                // a failed round shouldn't stop the monitor
+                if let Err(e) = sync_account_once(account_handle, matches).await {
+                    print_error(e);
+                }
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => break,
+                    _ = tokio::time::sleep(interval) => {}
+                }
+            },
+            None => sync_account_once(account_handle, matches).await?,
         }
-        let synced = sync.execute().await?;
-        for address in synced.addresses() {
-            print_address(account_handle, address).await;
+    }
+    Ok(())
+}
+
+// runs a single sync of the account with the `sync` command options, building a fresh synchronizer each time
+async fn sync_account_once(account_handle: &AccountHandle, matches: &ArgMatches) -> Result<()> {
+    let mut sync = account_handle.sync().await;
+    if let Some(gap_limit) = matches.value_of("gap") {
+        if let Ok(limit) = gap_limit.parse::<usize>() {
+            log::info!("Syncing with gap limit {}", limit);
+            sync = sync.gap_limit(limit);
+        } else {
+            return Err(anyhow::anyhow!("Gap limit must be a number"));
         }
-        for message in synced.messages() {
-            print_message(message);
+    }
+    if let Some(address_index) = matches.value_of("address-index") {
+        let address_index = address_index
+            .parse::<usize>()
+            .map_err(|_| anyhow::anyhow!("Address index must be a number"))?;
+        sync = sync.address_index(address_index);
+    }
+    if matches.is_present("skip-persistence") {
+        sync = sync.skip_persistence();
+    }
+    let synced = with_spinner("Syncing...", sync.execute()).await.map_err(node_error)?;
+    for address in synced.addresses() {
+        print_address(account_handle, address).await;
+    }
+    for message in synced.messages() {
+        print_message(message);
+    }
+    Ok(())
+}
+
+// syncs the account in rounds, saving the progress so an interrupted sync resumes after the last used address found;
+// the wallet library can't bound a round: it keeps discovering addresses until a whole window of `window` addresses
+// is unused, so a round may cover many more addresses than the window
+async fn sync_account_resumable(account_handle: &AccountHandle, window: usize, config: &mut Config) -> Result<()> {
+    let account_id = account_handle.id().await;
+    let mut address_index = config.sync_progress.get(&account_id).copied().unwrap_or_default();
+    if address_index > 0 {
+        log::info!("Resuming sync from address index {}", address_index);
+    }
+    loop {
+        log::info!(
+            "Syncing from address index {} with a discovery window of {} addresses",
+            address_index,
+            window
+        );
+        let synced = account_handle
+            .sync()
+            .await
+            .address_index(address_index)
+            .gap_limit(window)
+            .execute()
+            .await
+            .map_err(node_error)?;
+        // an address is used once it received an output, even if that output was spent since
+        let last_used = synced
+            .addresses()
+            .iter()
+            .filter(|address| !address.outputs().is_empty())
+            .map(|address| *address.key_index())
+            .max();
+        match last_used {
+            Some(index) if index >= address_index => {
+                address_index = index + 1;
+                config.sync_progress.insert(account_id.clone(), address_index);
+                config.save()?;
+            }
+            // no used address after the cursor, the end of the account was reached
+            _ => break,
         }
     }
+    config.sync_progress.remove(&account_id);
+    config.save()?;
+    log::info!("Sync complete");
     Ok(())
 }
 
 // `address` command
 async fn generate_address_command(account_handle: &AccountHandle, matches: &ArgMatches) -> Result<()> {
-    if matches.subcommand_matches("address").is_some() {
-        let address = account_handle.generate_address().await?;
-        print_address(account_handle, &address).await;
+    if let Some(matches) = matches.subcommand_matches("address") {
+        let count = match matches.value_of("count") {
+            Some(count) => count
+                .parse::<usize>()
+                .ok()
+                .filter(|count| (1..=MAX_GENERATED_ADDRESSES).contains(count))
+                .ok_or_else(|| anyhow::anyhow!("Count must be a number between 1 and {}", MAX_GENERATED_ADDRESSES))?,
+            None => 1,
+        };
+        for _ in 0..count {
+            let address = account_handle.generate_address().await?;
+            print_address(account_handle, &address).await;
+            if matches.is_present("qr") {
+                print_qr_code(&address.address().to_bech32())?;
+            }
+        }
     }
     Ok(())
 }
 
 // `balance` command
-async fn balance_command(account_handle: &AccountHandle, matches: &ArgMatches) -> Result<()> {
-    if matches.subcommand_matches("balance").is_some() {
+// gets the price of 1 Mi in the currency, reusing a recent price so repeated commands don't hammer the API
+async fn iota_price(api: &str, currency: &str) -> Result<f64> {
+    if let Some((cached_currency, fetched_at, price)) = &*PRICE_CACHE.lock().unwrap() {
+        if cached_currency == currency && fetched_at.elapsed() < PRICE_CACHE_DURATION {
+            return Ok(*price);
+        }
+    }
+    let response: serde_json::Value = reqwest::get(api.replace("{currency}", currency))
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let price = response["iota"][currency]
+        .as_f64()
+        .ok_or_else(|| anyhow::anyhow!("no `{}` price in the API response", currency))?;
+    *PRICE_CACHE.lock().unwrap() = Some((currency.to_string(), Instant::now(), price));
+    Ok(price)
+}
+
+// gets the ledger balance of every account address from a single node, through a client built for that node only so
+// the account nodes are left untouched
+async fn node_balances(account_handle: &AccountHandle, node: &str) -> Result<Vec<(String, u64)>> {
+    let client = get_nodes_client(&[node]).await?;
+    let mut balances = Vec::new();
+    for address in account_handle.read().await.addresses() {
+        let bech32_address = address.address().to_bech32();
+        let balance = client
+            .get_address()
+            .balance(&bech32_address)
+            .await
+            .map_err(|e| anyhow::anyhow!("Could not get the balance from {}: {}", node, e))?
+            .balance;
+        balances.push((bech32_address, balance));
+    }
+    Ok(balances)
+}
+
+async fn balance_command(account_handle: &AccountHandle, matches: &ArgMatches, config: &Config) -> Result<()> {
+    if let Some(matches) = matches.subcommand_matches("balance") {
+        let json = matches.is_present("json") || output::has_output_file();
+        if let Some(node) = matches.value_of("node") {
+            let mut balances = node_balances(account_handle, node).await?;
+            balances.retain(|(_, balance)| *balance > 0);
+            balances.sort_by(|a, b| b.1.cmp(&a.1));
+            let total: u64 = balances.iter().map(|(_, balance)| balance).sum();
+            if json {
+                let addresses: Vec<_> = balances
+                    .iter()
+                    .map(|(address, balance)| serde_json::json!({ "address": address, "balance": balance }))
+                    .collect();
+                return output::write_json(&serde_json::json!({
+                    "node": node,
+                    "total": total,
+                    "addresses": addresses,
+                }));
+            }
+            println!("Total according to {}: {}", node, units::format_amount(total));
+            if matches.is_present("by-address") {
+                for (address, balance) in balances {
+                    println!("--- {} {:>20}", address, units::format_amount(balance));
+                }
+            }
+            return Ok(());
+        }
         let account = account_handle.read().await;
-        println!("{:?}", account.balance().await?);
+        if matches.is_present("by-address") {
+            let mut balances = Vec::new();
+            for address in account.addresses() {
+                let balance = account.address_available_balance(address).await.map_err(node_error)?;
+                if balance > 0 {
+                    balances.push((address.address().to_bech32(), balance));
+                }
+            }
+            balances.sort_by(|a, b| b.1.cmp(&a.1));
+            if json {
+                let balances: Vec<_> = balances
+                    .iter()
+                    .map(|(address, balance)| serde_json::json!({ "address": address, "balance": balance }))
+                    .collect();
+                return output::write_json(&serde_json::json!(balances));
+            }
+            if balances.is_empty() {
+                println!("No addresses with balance found");
+                return Ok(());
+            }
+            let total: u64 = balances.iter().map(|(_, balance)| balance).sum();
+            for (address, balance) in balances {
+                println!(
+                    "{} {:>20} {:>6.2}%",
+                    address,
+                    units::format_amount(balance),
+                    balance as f64 * 100.0 / total as f64
+                );
+            }
+        } else {
+            let balance = account.balance().await.map_err(node_error)?;
+            // the fiat value is optional, so a failing price API only costs a warning
+            let fiat = match matches.value_of("fiat") {
+                Some(currency) => {
+                    let currency = currency.to_lowercase();
+                    match iota_price(config.price_api.as_deref().unwrap_or(DEFAULT_PRICE_API), &currency).await {
+                        Ok(price) => Some((currency, balance.available as f64 / 1_000_000.0 * price)),
+                        Err(e) => {
+                            log::warn!("WARNING: could not get the IOTA price: {}", e);
+                            None
+                        }
+                    }
+                }
+                None => None,
+            };
+            if json {
+                let mut value = serde_json::json!({
+                    "total": balance.total,
+                    "available": balance.available,
+                    "incoming": balance.incoming,
+                    "outgoing": balance.outgoing,
+                });
+                if let Some((currency, fiat_value)) = &fiat {
+                    value["fiat"] = serde_json::json!({ "currency": currency, "available": fiat_value });
+                }
+                output::write_json(&value)?;
+            } else {
+                println!("Total: {}", units::format_amount(balance.total));
+                println!("--- Available: {}", units::format_amount(balance.available));
+                println!("--- Incoming: {}", units::format_amount(balance.incoming));
+                println!("--- Outgoing: {}", units::format_amount(balance.outgoing));
+                if let Some((currency, fiat_value)) = fiat {
+                    println!("--- Available in {}: {:.2}", currency.to_uppercase(), fiat_value);
+                }
+            }
+        }
     }
     Ok(())
 }
 
+// parses a `--to ADDRESS:AMOUNT` transfer output
+fn parse_transfer_output(output: &str) -> Result<(AddressWrapper, NonZeroU64)> {
+    let (address, amount) = output
+        .split_once(':')
+        .ok_or_else(|| anyhow::anyhow!("Output {} must be formatted as ADDRESS:AMOUNT", output))?;
+    let amount =
+        NonZeroU64::new(units::parse_amount(amount)?).ok_or_else(|| anyhow::anyhow!("amount can't be zero"))?;
+    Ok((parse_address(address)?, amount))
+}
+
 // `transfer` command
 async fn transfer_command(account_handle: &AccountHandle, matches: &ArgMatches) -> Result<()> {
     if let Some(matches) = matches.subcommand_matches("transfer") {
-        let address = matches.value_of("address").unwrap().to_string();
-        let amount = matches.value_of("amount").unwrap();
-        if let Ok(address) = iota_wallet::address::parse(address) {
-            if let Ok(amount) = amount.parse::<u64>() {
-                let transfer = Transfer::builder(
-                    address,
-                    NonZeroU64::new(amount).ok_or_else(|| anyhow::anyhow!("amount can't be zero"))?,
-                    None,
-                )
-                .finish();
+        // every output is validated before anything is sent
+        let outputs = match (
+            matches.value_of("address"),
+            matches.value_of("amount"),
+            matches.values_of("to"),
+        ) {
+            (Some(address), Some(amount), None) => vec![parse_transfer_output(&format!("{}:{}", address, amount))?],
+            (None, None, Some(outputs)) => outputs.map(parse_transfer_output).collect::<Result<Vec<_>>>()?,
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Either ADDRESS AMOUNT or one or more `--to ADDRESS:AMOUNT` outputs are required"
+                ))
+            }
+        };
 
-                let message = account_handle.transfer(transfer).await?;
-                print_message(&message);
-            } else {
-                return Err(anyhow::anyhow!("Amount must be a number"));
+        // the summary shows the parsed values, so a typo can't hide behind the raw input
+        println!("Transfer from account `{}`:", account_handle.alias().await);
+        for (address, amount) in &outputs {
+            println!("--- {} to {}", units::format_amount(amount.get()), address.to_bech32());
+        }
+        let mut transfer = Transfer::builder_with_outputs(
+            outputs
+                .into_iter()
+                .map(|(address, amount)| TransferOutput::new(address, amount, None))
+                .collect(),
+        )?;
+        // without the flag the wallet library picks the remainder address itself
+        if let Some(remainder) = matches.value_of("remainder") {
+            let strategy = match remainder {
+                "change-address" => RemainderValueStrategy::ChangeAddress,
+                "reuse-address" => RemainderValueStrategy::ReuseAddress,
+                _ => RemainderValueStrategy::AccountAddress(account_handle.latest_address().await.address().clone()),
+            };
+            transfer = transfer.with_remainder_value_strategy(strategy);
+        }
+        if let Some(tag) = matches.value_of("tag") {
+            if tag.is_empty() || tag.len() > MAX_INDEX_LENGTH {
+                return Err(anyhow::anyhow!(
+                    "Tag must be between 1 and {} bytes long",
+                    MAX_INDEX_LENGTH
+                ));
             }
-        } else {
-            return Err(anyhow::anyhow!("Address must be a bech32 string"));
+            let data = match (matches.value_of("data"), matches.value_of("data-hex")) {
+                (Some(data), _) => data.as_bytes().to_vec(),
+                (None, Some(data)) => hex::decode(data.trim_start_matches("0x"))
+                    .map_err(|_| anyhow::anyhow!("Data must be a hex string"))?,
+                (None, None) => Vec::new(),
+            };
+            transfer = transfer.with_indexation(IndexationPayload::new(tag.as_bytes(), &data)?);
+        }
+        let transfer = transfer.finish();
+
+        if matches.is_present("dry-run") {
+            println!("Dry run, nothing sent");
+            return Ok(());
+        }
+        if !matches.is_present("yes") && !Confirm::new().with_prompt("Send it?").default(false).interact()? {
+            println!("Transfer cancelled");
+            return Ok(());
+        }
+
+        let message = account_handle.transfer(transfer).await?;
+        print_message(&message);
+        if matches.is_present("await-confirmation") {
+            let timeout = match matches.value_of("timeout") {
+                Some(seconds) => seconds
+                    .parse::<u64>()
+                    .map_err(|_| anyhow::anyhow!("Timeout must be a number of seconds"))?,
+                None => 120,
+            };
+            await_confirmation(account_handle, message.id(), Duration::from_secs(timeout)).await?;
+        }
+    }
+    Ok(())
+}
+
+// polls the node until the message is referenced by a milestone or the timeout elapses
+async fn await_confirmation(account_handle: &AccountHandle, message_id: &MessageId, timeout: Duration) -> Result<()> {
+    let client = get_client(account_handle).await?;
+    let started = Instant::now();
+    log::info!("Waiting for confirmation...");
+    loop {
+        if let Ok(metadata) = client.get_message().metadata(message_id).await {
+            if let Some(index) = metadata.referenced_by_milestone_index {
+                println!(
+                    "Message referenced by milestone {} ({:?})",
+                    index, metadata.ledger_inclusion_state
+                );
+                return Ok(());
+            }
+        }
+        if started.elapsed() >= timeout {
+            println!("Message still not confirmed after {} seconds", timeout.as_secs());
+            return Ok(());
+        }
+        tokio::time::sleep(Duration::from_secs(2)).await;
+    }
+}
+
+// `send-from-file` command
+async fn send_from_file_command(account_handle: &AccountHandle, matches: &ArgMatches) -> Result<()> {
+    if let Some(matches) = matches.subcommand_matches("send-from-file") {
+        let path = matches.value_of("path").unwrap();
+        let content = fs::read_to_string(path).map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path, e))?;
+        let account_address = account_handle.latest_address().await.address().to_bech32();
+        let (outputs, problems) = batch::parse(&content, Some(bech32_hrp(&account_address)));
+        if !problems.is_empty() {
+            for problem in &problems {
+                println!("{}", problem);
+            }
+            return Err(anyhow::anyhow!("{} problems found in {}", problems.len(), path));
+        }
+        if matches.is_present("parse-only") {
+            println!("{} outputs parsed, no problems found", outputs.len());
+            return Ok(());
+        }
+
+        let confirm_each = matches.is_present("confirm-each");
+        let mut confirmed = Vec::new();
+        for output in outputs {
+            if confirm_each {
+                let send = Confirm::new()
+                    .with_prompt(format!(
+                        "line {}: send {} to {}?",
+                        output.line,
+                        output.amount,
+                        output.address.to_bech32()
+                    ))
+                    .default(false)
+                    .interact()?;
+                if !send {
+                    println!("line {}: skipped", output.line);
+                    continue;
+                }
+            }
+            confirmed.push(output);
+        }
+
+        // the outputs are sent in as few transactions as possible, leaving room for the remainder output
+        for chunk in confirmed.chunks(MAX_TRANSFER_OUTPUTS) {
+            // skipped lines leave gaps, so the lines sent are listed one by one
+            let line_numbers: Vec<String> = chunk.iter().map(|output| output.line.to_string()).collect();
+            let lines = match line_numbers.as_slice() {
+                [line] => format!("line {}", line),
+                _ => format!("lines {}", line_numbers.join(", ")),
+            };
+            let transfer = Transfer::builder_with_outputs(
+                chunk
+                    .iter()
+                    .map(|output| TransferOutput::new(output.address.clone(), output.amount, None))
+                    .collect(),
+            )?
+            .finish();
+            let message = account_handle
+                .transfer(transfer)
+                .await
+                .map_err(|e| anyhow::anyhow!("{}: {}", lines, e))?;
+            println!("{}: sent {} outputs in message {}", lines, chunk.len(), message.id());
         }
     }
     Ok(())
@@ -175,14 +1093,72 @@ enum ReplayAction {
     Reattach,
 }
 
+impl FromStr for ReplayAction {
+    type Err = anyhow::Error;
+
+    fn from_str(action: &str) -> Result<Self> {
+        match action {
+            "promote" => Ok(Self::Promote),
+            "retry" => Ok(Self::Retry),
+            "reattach" => Ok(Self::Reattach),
+            _ => Err(anyhow::anyhow!("Action must be one of promote, retry or reattach")),
+        }
+    }
+}
+
+// checks that the replay action applies to the message before acting on it
+async fn check_replay_eligibility(
+    account_handle: &AccountHandle,
+    action: &ReplayAction,
+    message_id: &MessageId,
+) -> Result<()> {
+    let message = account_handle
+        .read()
+        .await
+        .get_message(message_id)
+        .await
+        .ok_or_else(|| anyhow::anyhow!("Message not found"))?;
+    if message.confirmed().unwrap_or_default() {
+        return Err(anyhow::anyhow!("Message already confirmed, nothing to do"));
+    }
+
+    // a message unknown to the node can still be reattached, but not promoted
+    let metadata = get_client(account_handle)
+        .await?
+        .get_message()
+        .metadata(message_id)
+        .await
+        .ok();
+    let should_promote = metadata.as_ref().and_then(|m| m.should_promote).unwrap_or_default();
+    let should_reattach = match &metadata {
+        Some(metadata) => metadata.should_reattach.unwrap_or_default(),
+        None => true,
+    };
+    match action {
+        ReplayAction::Promote if !should_promote => Err(anyhow::anyhow!("Message not eligible for promotion")),
+        ReplayAction::Reattach if !should_reattach => Err(anyhow::anyhow!("Message not eligible for reattachment")),
+        ReplayAction::Retry if !(should_promote || should_reattach) => {
+            Err(anyhow::anyhow!("Message not eligible for promotion or reattachment"))
+        }
+        _ => Ok(()),
+    }
+}
+
+// applies the replay action to a message
+async fn replay(account_handle: &AccountHandle, action: &ReplayAction, message_id: &MessageId) -> Result<Message> {
+    let message = match action {
+        ReplayAction::Promote => account_handle.promote(message_id).await?,
+        ReplayAction::Retry => account_handle.retry(message_id).await?,
+        ReplayAction::Reattach => account_handle.reattach(message_id).await?,
+    };
+    Ok(message)
+}
+
 // promotes, retries or reattaches a message
 async fn replay_message(account_handle: &AccountHandle, action: ReplayAction, message_id: &str) -> Result<()> {
     if let Ok(message_id) = MessageId::from_str(message_id) {
-        let message = match action {
-            ReplayAction::Promote => account_handle.promote(&message_id).await?,
-            ReplayAction::Retry => account_handle.retry(&message_id).await?,
-            ReplayAction::Reattach => account_handle.reattach(&message_id).await?,
-        };
+        check_replay_eligibility(account_handle, &action, &message_id).await?;
+        let message = replay(account_handle, &action, &message_id).await?;
         print_message(&message);
     } else {
         println!("Message id must be a hex string of length 64");
@@ -217,13 +1193,539 @@ async fn reattach_message_command(account_handle: &AccountHandle, matches: &ArgM
     Ok(())
 }
 
+// gets the balance of an account address, or `None` if the address doesn't belong to the account
+async fn address_balance(account_handle: &AccountHandle, bech32_address: &str) -> Option<u64> {
+    account_handle
+        .read()
+        .await
+        .addresses()
+        .iter()
+        .find(|address| address.address().to_bech32() == bech32_address)
+        .map(|address| address.balance())
+}
+
+// `watch-address` command
+async fn watch_address_command(account_handle: &AccountHandle, matches: &ArgMatches) -> Result<()> {
+    if let Some(matches) = matches.subcommand_matches("watch-address") {
+        let bech32_address = &normalize_address(matches.value_of("address").unwrap())?;
+        let interval = match matches.value_of("interval") {
+            Some(interval) => interval
+                .parse::<u64>()
+                .map_err(|_| anyhow::anyhow!("Interval must be a number of seconds"))?,
+            None => 10,
+        };
+        let mut balance = address_balance(account_handle, bech32_address)
+            .await
+            .ok_or_else(|| anyhow::anyhow!("Address doesn't belong to this account"))?;
+        println!(
+            "Watching {} (balance: {}), press Ctrl-C to stop",
+            bech32_address, balance
+        );
+
+        loop {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => break,
+                _ = tokio::time::sleep(Duration::from_secs(interval)) => {}
+            }
+            // a failed round shouldn't stop the watch
+            if let Err(e) = account_handle.sync().await.execute().await {
+                print_error(node_error(e));
+                continue;
+            }
+            let new_balance = address_balance(account_handle, bech32_address)
+                .await
+                .unwrap_or_default();
+            if new_balance != balance {
+                let change = format!(
+                    "Balance of {} changed from {} to {}",
+                    bech32_address, balance, new_balance
+                );
+                if !show_notification("CLI Wallet", &change) {
+                    println!("{}", change);
+                }
+                balance = new_balance;
+            }
+        }
+    }
+    Ok(())
+}
+
+// prints the confirmed deposits referenced after the cursor milestone, returning the advanced cursor
+async fn print_new_deposits(
+    account_handle: &AccountHandle,
+    client: &Client,
+    cursor: u32,
+    seen: &mut HashSet<MessageId>,
+) -> Result<u32> {
+    let messages = account_handle
+        .read()
+        .await
+        .list_messages(0, 0, Some(MessageType::Received))
+        .await?;
+    let mut latest = cursor;
+    for message in messages {
+        if !message.confirmed().unwrap_or_default() || !seen.insert(*message.id()) {
+            continue;
+        }
+        let milestone_index = match client.get_message().metadata(message.id()).await {
+            Ok(metadata) => metadata.referenced_by_milestone_index,
+            Err(_) => {
+                // retry on the next round
+                seen.remove(message.id());
+                continue;
+            }
+        };
+        if let Some(milestone_index) = milestone_index.filter(|index| *index > cursor) {
+            println!(
+                "DEPOSIT {} value {} at milestone {}",
+                message.id(),
+                message_value(&message).unwrap_or_default(),
+                milestone_index
+            );
+            latest = latest.max(milestone_index);
+        }
+    }
+    if latest > cursor {
+        println!("--- Cursor advanced to milestone {}", latest);
+    }
+    Ok(latest)
+}
+
+// `poll-deposits` command
+async fn poll_deposits_command(account_handle: &AccountHandle, matches: &ArgMatches) -> Result<()> {
+    if let Some(matches) = matches.subcommand_matches("poll-deposits") {
+        let interval = match matches.value_of("interval") {
+            Some(interval) => interval
+                .parse::<u64>()
+                .map_err(|_| anyhow::anyhow!("Interval must be a number of seconds"))?,
+            None => 10,
+        };
+        let client = get_client(account_handle).await?;
+        let mut cursor = match matches.value_of("since-milestone") {
+            Some(index) => index
+                .parse::<u32>()
+                .map_err(|_| anyhow::anyhow!("Milestone index must be a number"))?,
+            None => client.get_info().await?.nodeinfo.confirmed_milestone_index,
+        };
+        log::info!("Polling deposits after milestone {}, press Ctrl-C to stop", cursor);
+
+        // confirmed messages already checked against the cursor
+        let mut seen = HashSet::new();
+        loop {
+            // a failed round shouldn't stop the poll
+            match account_handle.sync().await.execute().await {
+                Ok(_) => cursor = print_new_deposits(account_handle, &client, cursor, &mut seen).await?,
+                Err(e) => print_error(node_error(e)),
+            }
+
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => break,
+                _ = tokio::time::sleep(Duration::from_secs(interval)) => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+// `feed` command
+async fn feed_command(account_handle: &AccountHandle, matches: &ArgMatches) -> Result<()> {
+    if let Some(matches) = matches.subcommand_matches("feed") {
+        // `None` follows every account
+        let account_id = if matches.is_present("all") {
+            None
+        } else {
+            Some(account_handle.id().await)
+        };
+
+        let account_id_ = account_id.clone();
+        let new_transaction_listener = on_new_transaction(move |event| {
+            if account_id_.as_ref().map_or(true, |id| id == &event.account_id) {
+                println!(
+                    "[{}] NEW {} value {} on {}",
+                    Local::now().format("%H:%M:%S"),
+                    event.message.id(),
+                    message_value(&event.message).unwrap_or_default(),
+                    event.account_id
+                );
+            }
+        })
+        .await;
+        let confirmation_listener = on_confirmation_state_change(move |event| {
+            if account_id.as_ref().map_or(true, |id| id == &event.account_id) {
+                println!(
+                    "[{}] {} {} on {}",
+                    Local::now().format("%H:%M:%S"),
+                    if event.confirmed { "CONFIRMED" } else { "FAILED" },
+                    event.message.id(),
+                    event.account_id
+                );
+            }
+        })
+        .await;
+
+        log::info!("Following new and confirmed messages, press Ctrl-C to stop");
+        tokio::signal::ctrl_c().await?;
+        remove_new_transaction_listener(&new_transaction_listener).await;
+        remove_confirmation_state_change_listener(&confirmation_listener).await;
+    }
+    Ok(())
+}
+
+// `consolidate` command
+async fn consolidate_command(account_handle: &AccountHandle, matches: &ArgMatches) -> Result<()> {
+    if let Some(matches) = matches.subcommand_matches("consolidate") {
+        let threshold = match matches.value_of("threshold") {
+            Some(threshold) => Some(units::parse_amount(threshold)?),
+            None => None,
+        };
+        // addresses holding at least two unspent outputs below the threshold, with those outputs
+        let mut candidates: Vec<(AddressWrapper, Vec<AddressOutput>)> = Vec::new();
+        for address in account_handle.read().await.addresses() {
+            let outputs: Vec<AddressOutput> = address
+                .outputs()
+                .values()
+                .filter(|output| !output.is_spent() && threshold.map_or(true, |threshold| *output.amount() < threshold))
+                .cloned()
+                .collect();
+            if outputs.len() > 1 {
+                candidates.push((address.address().clone(), outputs));
+            }
+        }
+        if candidates.is_empty() {
+            println!("No outputs to consolidate");
+            return Ok(());
+        }
+
+        // the preview and the consolidation use the same outputs
+        let dry_run = matches.is_present("dry-run");
+        for (address, outputs) in candidates {
+            let value: u64 = outputs.iter().map(|output| *output.amount()).sum();
+            println!("ADDRESS {}", address.to_bech32());
+            for output in &outputs {
+                println!("--- Output: {}", units::format_amount(*output.amount()));
+            }
+            println!("--- Consolidated output: {}", units::format_amount(value));
+            if dry_run {
+                continue;
+            }
+            let inputs = outputs.len();
+            let transfer = Transfer::builder(address.clone(), NonZeroU64::new(value).unwrap(), None)
+                .with_input(address, outputs)
+                .with_remainder_value_strategy(RemainderValueStrategy::ReuseAddress)
+                .finish();
+            match account_handle.transfer(transfer).await {
+                Ok(message) => println!("Consolidated {} outputs in message {}", inputs, message.id()),
+                Err(e) => print_error(node_error(e)),
+            }
+        }
+    }
+    Ok(())
+}
+
+// `triage` command
+async fn triage_command(account_handle: &AccountHandle, matches: &ArgMatches) -> Result<()> {
+    if let Some(matches) = matches.subcommand_matches("triage") {
+        let older_than = match matches.value_of("older-than") {
+            Some(seconds) => seconds
+                .parse::<i64>()
+                .map_err(|_| anyhow::anyhow!("Age threshold must be a number of seconds"))?,
+            None => 0,
+        };
+        let min_value = match matches.value_of("min-value") {
+            Some(value) => units::parse_amount(value)?,
+            None => 0,
+        };
+        let action = ReplayAction::from_str(matches.value_of("action").unwrap())?;
+
+        let now = Utc::now();
+        let messages: Vec<Message> = account_handle
+            .read()
+            .await
+            .list_messages(0, 0, Some(MessageType::Unconfirmed))
+            .await?
+            .into_iter()
+            .filter(|message| {
+                now.signed_duration_since(*message.timestamp()).num_seconds() >= older_than
+                    && message_value(message).unwrap_or_default() >= min_value
+            })
+            .collect();
+        if messages.is_empty() {
+            println!("No pending messages match the thresholds");
+            return Ok(());
+        }
+
+        for message in &messages {
+            println!(
+                "{} (value: {})",
+                message.id(),
+                message_value(message).unwrap_or_default()
+            );
+        }
+        let proceed = Confirm::new()
+            .with_prompt(format!(
+                "Apply `{}` to {} messages?",
+                matches.value_of("action").unwrap(),
+                messages.len()
+            ))
+            .default(false)
+            .interact()?;
+        if !proceed {
+            return Ok(());
+        }
+
+        for message in &messages {
+            // the message may have been confirmed since it was listed
+            let result = match check_replay_eligibility(account_handle, &action, message.id()).await {
+                Ok(()) => match replay(account_handle, &action, message.id()).await {
+                    Ok(replayed) => format!("ok, new message {}", replayed.id()),
+                    Err(e) => format!("failed: {}", e),
+                },
+                Err(e) => format!("skipped: {}", e),
+            };
+            println!("{} | {}", message.id(), result);
+        }
+    }
+    Ok(())
+}
+
+// `reattach-all` command
+async fn reattach_all_command(account_handle: &AccountHandle, matches: &ArgMatches) -> Result<()> {
+    if let Some(matches) = matches.subcommand_matches("reattach-all") {
+        let messages: Vec<Message> = account_handle
+            .read()
+            .await
+            .list_messages(0, 0, Some(MessageType::Unconfirmed))
+            .await?
+            .into_iter()
+            .filter(|message| message_value(message).is_some())
+            .collect();
+        if messages.is_empty() {
+            println!("No pending transactions");
+            return Ok(());
+        }
+
+        for message in &messages {
+            if matches.is_present("dry-run") {
+                println!(
+                    "{} (value: {})",
+                    message.id(),
+                    units::format_amount(message_value(message).unwrap_or_default())
+                );
+                continue;
+            }
+            // the message may have been confirmed since it was listed
+            let result = match check_replay_eligibility(account_handle, &ReplayAction::Reattach, message.id()).await {
+                Ok(()) => match replay(account_handle, &ReplayAction::Reattach, message.id()).await {
+                    Ok(reattached) => format!("reattached as {}", reattached.id()),
+                    Err(e) => format!("failed: {}", e),
+                },
+                Err(e) => format!("skipped: {}", e),
+            };
+            println!("{} | {}", message.id(), result);
+        }
+    }
+    Ok(())
+}
+
+// `refresh-message` command
+async fn refresh_message_command(account_handle: &AccountHandle, matches: &ArgMatches) -> Result<()> {
+    if let Some(matches) = matches.subcommand_matches("refresh-message") {
+        let message_id = MessageId::from_str(matches.value_of("id").unwrap())
+            .map_err(|_| anyhow::anyhow!("Message id must be a hex string of length 64"))?;
+        let stored_confirmed = account_handle
+            .read()
+            .await
+            .get_message(&message_id)
+            .await
+            .ok_or_else(|| anyhow::anyhow!("Message not found"))?
+            .confirmed()
+            .unwrap_or_default();
+
+        let metadata = get_client(account_handle)
+            .await?
+            .get_message()
+            .metadata(&message_id)
+            .await
+            .map_err(|e| anyhow::anyhow!("Could not get the message state from the node: {}", e))?;
+        // like the wallet library, a conflicting message referenced by a milestone isn't counted as confirmed
+        let confirmed = matches!(
+            metadata.ledger_inclusion_state,
+            Some(LedgerInclusionStateDto::Included) | Some(LedgerInclusionStateDto::NoTransaction)
+        );
+        match metadata.referenced_by_milestone_index {
+            Some(index) => println!(
+                "Referenced by milestone {} ({:?})",
+                index, metadata.ledger_inclusion_state
+            ),
+            None => println!("Not referenced by a milestone yet"),
+        }
+        // the wallet library can't update a single stored message, only a whole account sync does, so it's only run
+        // when the stored state is outdated
+        if confirmed != stored_confirmed {
+            log::info!("Stored state is outdated, syncing the whole account");
+            account_handle.sync().await.execute().await.map_err(node_error)?;
+        }
+        if let Some(message) = account_handle.read().await.get_message(&message_id).await {
+            print_message(&message);
+        }
+    }
+    Ok(())
+}
+
+// `rebroadcast` command
+async fn rebroadcast_message_command(account_handle: &AccountHandle, matches: &ArgMatches) -> Result<()> {
+    if let Some(matches) = matches.subcommand_matches("rebroadcast") {
+        let message_id = MessageId::from_str(matches.value_of("id").unwrap())
+            .map_err(|_| anyhow::anyhow!("Message id must be a hex string of length 64"))?;
+        if account_handle.read().await.get_message(&message_id).await.is_none() {
+            return Err(anyhow::anyhow!("Message not found"));
+        }
+
+        // the message is fetched from a node that still knows it and submitted to the ones that lost it; the stored
+        // wallet message can't be turned back into the exact signed message, so a single node account has no source
+        let client_options = account_handle.client_options().await;
+        if client_options
+            .primary_node()
+            .iter()
+            .chain(client_options.nodes())
+            .count()
+            < 2
+        {
+            return Err(anyhow::anyhow!(
+                "Rebroadcasting needs another account node that still knows the message, use `reattach` to issue a \
+                new one"
+            ));
+        }
+        let mut message = None;
+        let mut missing = Vec::new();
+        for node in client_options.primary_node().iter().chain(client_options.nodes()) {
+            let client = get_nodes_client(&[node.url.as_str()]).await?;
+            match client.get_message().data(&message_id).await {
+                Ok(data) => message = Some(data),
+                Err(_) => missing.push((node.url.to_string(), client)),
+            }
+        }
+        let message = message
+            .ok_or_else(|| anyhow::anyhow!("No node knows the message anymore, use `reattach` to issue a new one"))?;
+        if missing.is_empty() {
+            println!("Every node already knows the message");
+        }
+        for (url, client) in missing {
+            match client.post_message(&message).await {
+                Ok(_) => println!("{}: accepted", url),
+                Err(e) => println!("{}: rejected ({})", url, e),
+            }
+        }
+    }
+    Ok(())
+}
+
+// `info` command
+async fn info_command(account_handle: &AccountHandle, matches: &ArgMatches) -> Result<()> {
+    if matches.subcommand_matches("info").is_some() {
+        let client_options = account_handle.client_options().await;
+        let nodes: Vec<&str> = client_options
+            .primary_node()
+            .iter()
+            .chain(client_options.nodes())
+            .map(|node| node.url.as_str())
+            .collect();
+        println!("ACCOUNT {}", account_handle.alias().await);
+        println!("--- Id: {}", account_handle.id().await);
+        println!(
+            "--- Signer type: {}",
+            signer_type_name(&account_handle.signer_type().await)
+        );
+        println!("--- Nodes: {}", nodes.join(", "));
+        let account = account_handle.read().await;
+        println!("--- Addresses: {}", account.addresses().len());
+        println!(
+            "--- Total balance: {}",
+            units::format_amount(account.balance().await?.total)
+        );
+    }
+    Ok(())
+}
+
+// `node-info` command
+async fn node_info_command(account_handle: &AccountHandle, matches: &ArgMatches) -> Result<()> {
+    if matches.subcommand_matches("node-info").is_some() {
+        let info = get_client(account_handle)
+            .await?
+            .get_info()
+            .await
+            .map_err(|e| anyhow::anyhow!("Could not reach the account nodes: {}", e))?;
+        println!("NODE {}", info.url);
+        println!("--- Name: {} {}", info.nodeinfo.name, info.nodeinfo.version);
+        println!("--- Healthy: {}", info.nodeinfo.is_healthy);
+        println!("--- Network id: {}", info.nodeinfo.network_id);
+        println!("--- Bech32 HRP: {}", info.nodeinfo.bech32_hrp);
+        println!("--- Latest milestone: {}", info.nodeinfo.latest_milestone_index);
+        println!("--- Confirmed milestone: {}", info.nodeinfo.confirmed_milestone_index);
+        println!("--- Pruning index: {}", info.nodeinfo.pruning_index);
+    }
+    Ok(())
+}
+
 // `set-node` command
 async fn set_node_command(account_handle: &AccountHandle, matches: &ArgMatches) -> Result<()> {
     if let Some(matches) = matches.subcommand_matches("set-node") {
-        let node = matches.value_of("node").unwrap();
-        account_handle
-            .set_client_options(ClientOptionsBuilder::new().with_nodes(&[node])?.build()?)
-            .await?;
+        let primary_node = matches.value_of("primary");
+        let mut nodes: Vec<&str> = primary_node
+            .into_iter()
+            .chain(matches.values_of("node").unwrap())
+            .collect();
+        nodes = dedup_nodes(&nodes);
+        let invalid: Vec<&str> = nodes
+            .iter()
+            .copied()
+            .filter(|node| reqwest::Url::parse(node).is_err())
+            .collect();
+        if !invalid.is_empty() {
+            return Err(anyhow::anyhow!("Invalid node URL: {}", invalid.join(", ")));
+        }
+
+        // the primary node is tried first, the others are fallbacks
+        let mut builder = ClientOptionsBuilder::new();
+        let fallback_nodes = match primary_node {
+            Some(_) => {
+                builder = builder.with_primary_node(nodes[0])?;
+                &nodes[1..]
+            }
+            None => &nodes[..],
+        };
+        if !fallback_nodes.is_empty() {
+            builder = builder.with_nodes(fallback_nodes)?;
+        }
+        let client_options = builder.build()?;
+
+        // pointing the account to a node of another network breaks its addresses and transfers
+        let new_network_id = get_nodes_client(&nodes).await?.get_info().await?.nodeinfo.network_id;
+        let current_network_id = match get_client(account_handle).await {
+            Ok(client) => client.get_info().await.ok().map(|info| info.nodeinfo.network_id),
+            Err(_) => None,
+        };
+        match current_network_id {
+            Some(current_network_id) if current_network_id != new_network_id => {
+                println!(
+                    "The new node is on network `{}` but the account is on network `{}`",
+                    new_network_id, current_network_id
+                );
+                let switch = matches.is_present("force")
+                    || Confirm::new()
+                        .with_prompt("Switch the account to the other network anyway?")
+                        .default(false)
+                        .interact()?;
+                if !switch {
+                    println!("Node not changed");
+                    return Ok(());
+                }
+            }
+            Some(_) => {}
+            None => println!("Could not reach the current node, the network can't be compared"),
+        }
+
+        account_handle.set_client_options(client_options).await?;
     }
     Ok(())
 }
@@ -237,26 +1739,137 @@ async fn set_alias_command(account_handle: &AccountHandle, matches: &ArgMatches)
     Ok(())
 }
 
+// `explain-address` command
+fn explain_address_command(matches: &ArgMatches) -> Result<()> {
+    if let Some(matches) = matches.subcommand_matches("explain-address") {
+        let address = matches.value_of("address").unwrap();
+        let (hrp, data, _) =
+            bech32::decode(address).map_err(|e| anyhow::anyhow!("Address must be a bech32 string: {}", e))?;
+        let payload = Vec::<u8>::from_base32(&data)?;
+        let (kind, hash) = payload
+            .split_first()
+            .ok_or_else(|| anyhow::anyhow!("Address has an empty payload"))?;
+        println!("ADDRESS {}", address);
+        println!("--- Human-readable part: {} (the network the address belongs to)", hrp);
+        println!(
+            "--- Address type: {} ({})",
+            kind,
+            match kind {
+                0 => "Ed25519, the hash of a public key",
+                _ => "unknown",
+            }
+        );
+        println!("--- Payload: 0x{} (the public key hash)", hex::encode(hash));
+        println!(
+            "--- Checksum: {} (the last 6 characters, detecting typos)",
+            &address[address.len() - 6..]
+        );
+    }
+    Ok(())
+}
+
+// `faucet` command
+async fn faucet_command(account_handle: &AccountHandle, matches: &ArgMatches) -> Result<()> {
+    if let Some(matches) = matches.subcommand_matches("faucet") {
+        let latest_address = account_handle.latest_address().await.address().to_bech32();
+        let hrp = bech32_hrp(&latest_address);
+        if hrp == MAINNET_HRP {
+            return Err(anyhow::anyhow!("The faucet is not available on mainnet"));
+        }
+        let url = match matches.value_of("url") {
+            Some(url) => url,
+            None => {
+                let network_id = get_client(account_handle)
+                    .await?
+                    .get_info()
+                    .await
+                    .map_err(|e| anyhow::anyhow!("Could not get the network of the account nodes: {}", e))?
+                    .nodeinfo
+                    .network_id;
+                FAUCET_URLS
+                    .iter()
+                    .find(|(faucet_network_id, _)| *faucet_network_id == network_id)
+                    .map(|(_, url)| *url)
+                    .ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "No known faucet for network `{}`, pass its endpoint with --url",
+                            network_id
+                        )
+                    })?
+            }
+        };
+        let address = match matches.value_of("address") {
+            Some(address) => {
+                parse_address(address)?;
+                address.to_string()
+            }
+            None => account_handle.generate_address().await?.address().to_bech32(),
+        };
+        println!("Requesting funds for {}", address);
+        let response = reqwest::Client::new()
+            .post(url)
+            .json(&serde_json::json!({ "address": address }))
+            .send()
+            .await?;
+        let status = response.status();
+        let body = response.text().await?;
+        if status.is_success() {
+            println!("Faucet request accepted: {}", body);
+            log::info!("Run `sync` in a few seconds to see the funds");
+        } else {
+            return Err(anyhow::anyhow!("Faucet request failed ({}): {}", status, body));
+        }
+    }
+    Ok(())
+}
+
 // account prompt commands
-async fn account_commands(account_handle: &AccountHandle, matches: &ArgMatches) -> Result<()> {
+async fn account_commands(account_handle: &AccountHandle, matches: &ArgMatches, config: &mut Config) -> Result<()> {
+    let command = matches.subcommand_name().unwrap_or_default();
+    if NETWORK_COMMANDS.contains(&command) || command == "balance" {
+        simulate_latency().await;
+    }
     list_messages_command(account_handle, matches).await?;
-    list_addresses_command(account_handle, matches).await;
-    sync_account_command(account_handle, matches).await?;
+    export_history_command(account_handle, matches).await?;
+    balance_history_command(account_handle, matches).await?;
+    list_addresses_command(account_handle, matches).await?;
+    export_addresses_command(account_handle, matches).await?;
+    address_info_command(account_handle, matches).await?;
+    is_address_mine_command(account_handle, matches).await?;
+    sync_account_command(account_handle, matches, config).await?;
     generate_address_command(account_handle, matches).await?;
-    balance_command(account_handle, matches).await?;
+    balance_command(account_handle, matches, config).await?;
     transfer_command(account_handle, matches).await?;
+    send_from_file_command(account_handle, matches).await?;
     promote_message_command(account_handle, matches).await?;
     retry_message_command(account_handle, matches).await?;
     reattach_message_command(account_handle, matches).await?;
+    rebroadcast_message_command(account_handle, matches).await?;
+    refresh_message_command(account_handle, matches).await?;
+    watch_address_command(account_handle, matches).await?;
+    poll_deposits_command(account_handle, matches).await?;
+    feed_command(account_handle, matches).await?;
+    consolidate_command(account_handle, matches).await?;
+    triage_command(account_handle, matches).await?;
+    reattach_all_command(account_handle, matches).await?;
+    info_command(account_handle, matches).await?;
+    node_info_command(account_handle, matches).await?;
     set_node_command(account_handle, matches).await?;
     set_alias_command(account_handle, matches).await?;
+    faucet_command(account_handle, matches).await?;
+    explain_address_command(matches)?;
     Ok(())
 }
 
 // loop on the account prompt
-pub async fn account_prompt(account_cli: &App<'_>, account_handle: AccountHandle) {
+pub async fn account_prompt(account_cli: &App<'_>, account_handle: AccountHandle, config: &mut Config) {
+    // remembered so the next run can preselect or reopen this account
+    config.last_account = Some(account_handle.id().await);
+    if let Err(e) = config.save() {
+        print_error(e);
+    }
     loop {
-        let exit = account_prompt_internal(account_cli, account_handle.clone()).await;
+        let exit = account_prompt_internal(account_cli, account_handle.clone(), config).await;
         if exit {
             break;
         }
@@ -264,12 +1877,17 @@ pub async fn account_prompt(account_cli: &App<'_>, account_handle: AccountHandle
 }
 
 // loop on the account prompt
-pub async fn account_prompt_internal(account_cli: &App<'_>, account_handle: AccountHandle) -> bool {
+pub async fn account_prompt_internal(
+    account_cli: &App<'_>,
+    account_handle: AccountHandle,
+    config: &mut Config,
+) -> bool {
     let alias = account_handle.alias().await;
     let command: String = Input::new()
         .with_prompt(format!("Account `{}` command (h for help)", alias))
         .interact_text()
         .unwrap();
+    let command = config.expand_alias(&command);
 
     match command.as_str() {
         "h" => {
@@ -289,7 +1907,7 @@ pub async fn account_prompt_internal(account_cli: &App<'_>, account_handle: Acco
                         return true;
                     }
 
-                    if let Err(e) = account_commands(&account_handle, &matches).await {
+                    if let Err(e) = account_commands(&account_handle, &matches, config).await {
                         print_error(e);
                     }
                 }
@@ -0,0 +1,181 @@
+// Copyright 2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{bech32_hrp, parse_address};
+
+use iota_wallet::address::AddressWrapper;
+
+use std::{collections::HashSet, num::NonZeroU64};
+
+/// A transfer output read from a batch file.
+pub struct BatchOutput {
+    /// The line of the batch file the output was read from.
+    pub line: usize,
+    pub address: AddressWrapper,
+    pub amount: NonZeroU64,
+}
+
+/// Parses a batch file with one `bech32,amount` output per line.
+/// Empty lines and lines starting with `#` are skipped. Every problem found is returned along with its line
+/// number, so nothing should be sent unless the problem list is empty.
+/// Without a network HRP, every address must be on the network of the first valid address.
+pub fn parse(content: &str, network_hrp: Option<&str>) -> (Vec<BatchOutput>, Vec<String>) {
+    let mut network_hrp = network_hrp.map(|hrp| hrp.to_string());
+    let mut outputs = Vec::new();
+    let mut problems = Vec::new();
+    let mut seen = HashSet::new();
+
+    for (index, line) in content.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut fields = line
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|f| !f.is_empty());
+        let (address, amount) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(address), Some(amount), None) => (address, amount),
+            _ => {
+                problems.push(format!("line {}: expected `address,amount`", line_number));
+                continue;
+            }
+        };
+
+        let address = match parse_address(address) {
+            Ok(parsed) => match &network_hrp {
+                Some(hrp) if bech32_hrp(address) != hrp => {
+                    problems.push(format!(
+                        "line {}: address {} is not on the `{}` network",
+                        line_number, address, hrp
+                    ));
+                    continue;
+                }
+                Some(_) => parsed,
+                None => {
+                    network_hrp = Some(bech32_hrp(address).to_string());
+                    parsed
+                }
+            },
+            Err(e) => {
+                problems.push(format!("line {}: {}", line_number, e));
+                continue;
+            }
+        };
+        let amount = match amount.parse::<u64>().ok().and_then(NonZeroU64::new) {
+            Some(amount) => amount,
+            None => {
+                problems.push(format!("line {}: {} is not a valid amount", line_number, amount));
+                continue;
+            }
+        };
+        if !seen.insert(address.to_bech32()) {
+            problems.push(format!(
+                "line {}: duplicated address {}",
+                line_number,
+                address.to_bech32()
+            ));
+            continue;
+        }
+
+        outputs.push(BatchOutput {
+            line: line_number,
+            address,
+            amount,
+        });
+    }
+
+    (outputs, problems)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESS_1: &str = "atoi1qqqszqgpqyqszqgpqyqszqgpqyqszqgpqyqszqgpqyqszqgpqyqszaxehry";
+    const ADDRESS_2: &str = "atoi1qqpqyqszqgpqyqszqgpqyqszqgpqyqszqgpqyqszqgpqyqszqgpqyuwes7k";
+    const MAINNET_ADDRESS: &str = "iota1qqpsxqcrqvpsxqcrqvpsxqcrqvpsxqcrqvpsxqcrqvpsxqcrqvpsxjlsgyf";
+
+    fn problems(content: &str, network_hrp: Option<&str>) -> Vec<String> {
+        parse(content, network_hrp).1
+    }
+
+    #[test]
+    fn valid_file() {
+        let content = format!("# payouts\n\n{},1000000\n  {} 2000000  \n", ADDRESS_1, ADDRESS_2);
+        let (outputs, problems) = parse(&content, Some("atoi"));
+        assert!(problems.is_empty());
+        assert_eq!(outputs.len(), 2);
+        assert_eq!(outputs[0].line, 3);
+        assert_eq!(outputs[0].address.to_bech32(), ADDRESS_1);
+        assert_eq!(outputs[0].amount.get(), 1_000_000);
+        assert_eq!(outputs[1].line, 4);
+        assert_eq!(outputs[1].amount.get(), 2_000_000);
+    }
+
+    #[test]
+    fn malformed_line() {
+        let content = format!("{}\n{},1,2\n", ADDRESS_1, ADDRESS_2);
+        assert_eq!(
+            problems(&content, None),
+            vec!["line 1: expected `address,amount`", "line 2: expected `address,amount`"]
+        );
+    }
+
+    #[test]
+    fn invalid_address() {
+        let problems = problems("atoi1invalid,1000000\n", None);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].starts_with("line 1: "));
+    }
+
+    #[test]
+    fn network_mismatch() {
+        let content = format!("{},1000000\n", MAINNET_ADDRESS);
+        assert_eq!(
+            problems(&content, Some("atoi")),
+            vec![format!(
+                "line 1: address {} is not on the `atoi` network",
+                MAINNET_ADDRESS
+            )]
+        );
+    }
+
+    #[test]
+    fn network_defaults_to_the_first_address() {
+        let content = format!("{},1000000\n{},1000000\n", ADDRESS_1, MAINNET_ADDRESS);
+        assert_eq!(
+            problems(&content, None),
+            vec![format!(
+                "line 2: address {} is not on the `atoi` network",
+                MAINNET_ADDRESS
+            )]
+        );
+    }
+
+    #[test]
+    fn invalid_amounts() {
+        let content = format!(
+            "{},18446744073709551616\n{},0\n{},1Mi\n",
+            ADDRESS_1, ADDRESS_1, ADDRESS_1
+        );
+        assert_eq!(
+            problems(&content, None),
+            vec![
+                "line 1: 18446744073709551616 is not a valid amount",
+                "line 2: 0 is not a valid amount",
+                "line 3: 1Mi is not a valid amount",
+            ]
+        );
+    }
+
+    #[test]
+    fn duplicated_address() {
+        let content = format!("{},1000000\n{},2000000\n", ADDRESS_1, ADDRESS_1);
+        assert_eq!(
+            problems(&content, None),
+            vec![format!("line 2: duplicated address {}", ADDRESS_1)]
+        );
+    }
+}
@@ -0,0 +1,96 @@
+// Copyright 2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+const CONFIG_FILE_NAME: &str = "cli-config.json";
+
+/// CLI settings stored alongside the wallet database.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    #[serde(skip)]
+    path: PathBuf,
+    /// Account prompt command aliases, mapping the alias to the full command.
+    pub aliases: HashMap<String, String>,
+    /// The id of the account used when no account is given.
+    pub default_account: Option<String>,
+    /// The id of the account the account prompt was last opened for.
+    pub last_account: Option<String>,
+    /// Low balance alert thresholds, by account id.
+    pub balance_alerts: HashMap<String, u64>,
+    /// The address index an interrupted resumable sync resumes from, by account id.
+    pub sync_progress: HashMap<String, usize>,
+    /// Notification templates, by account id.
+    pub notifications: HashMap<String, NotificationTemplate>,
+    /// The price API used by `balance --fiat`, `{currency}` being replaced by the currency code.
+    pub price_api: Option<String>,
+}
+
+/// A notification template. The `{event}`, `{alias}`, `{amount}`, `{message_id}` and `{address}` placeholders are
+/// replaced when the notification is shown; placeholders that don't apply to the event are left empty.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct NotificationTemplate {
+    pub summary: String,
+    pub body: String,
+}
+
+impl NotificationTemplate {
+    /// Renders the summary and body of the notification.
+    pub fn render(&self, values: &[(&str, &str)]) -> (String, String) {
+        const PLACEHOLDERS: &[&str] = &["event", "alias", "amount", "message_id", "address"];
+        let render = |template: &str| {
+            PLACEHOLDERS.iter().fold(template.to_string(), |text, placeholder| {
+                let value = values
+                    .iter()
+                    .find(|(name, _)| name == placeholder)
+                    .map(|(_, value)| *value)
+                    .unwrap_or_default();
+                text.replace(&format!("{{{}}}", placeholder), value)
+            })
+        };
+        (render(&self.summary), render(&self.body))
+    }
+}
+
+impl Config {
+    /// Loads the config from the storage path, falling back to the default if it doesn't exist.
+    pub fn load(storage_path: &Path) -> Result<Self> {
+        let path = storage_path.join(CONFIG_FILE_NAME);
+        let mut config: Self = if path.exists() {
+            serde_json::from_str(&fs::read_to_string(&path)?)
+                .map_err(|e| anyhow::anyhow!("Invalid config file {:?}: {}", path, e))?
+        } else {
+            Self::default()
+        };
+        config.path = path;
+        Ok(config)
+    }
+
+    /// Saves the config to the file it was loaded from.
+    pub fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Expands a user-defined alias at the start of an account prompt command.
+    pub fn expand_alias(&self, command: &str) -> String {
+        let mut parts = command.splitn(2, ' ');
+        let name = parts.next().unwrap_or_default();
+        match (self.aliases.get(name), parts.next()) {
+            (Some(expansion), Some(args)) => format!("{} {}", expansion, args),
+            (Some(expansion), None) => expansion.clone(),
+            (None, _) => command.to_string(),
+        }
+    }
+}
@@ -4,27 +4,52 @@
 //! Wallet CLI
 //! Create a new account: `$ cargo run -- new --node http://localhost:14265`
 
+use chrono::Local;
 use clap::{load_yaml, App, AppSettings, ArgMatches};
-use dialoguer::{console::Term, theme::ColorfulTheme, Password, Select};
+use clap_generate::{
+    generate,
+    generators::{Bash, Fish, Zsh},
+};
+use config::Config;
+use dialoguer::{console::Term, theme::ColorfulTheme, Confirm, Password, Select};
+use iota_client::{api::GetAddressesBuilder, Client, Seed};
 use iota_wallet::{
     account::AccountHandle,
     account_manager::AccountManager,
+    address::AddressWrapper,
     client::ClientOptionsBuilder,
-    event::{on_balance_change, on_confirmation_state_change, on_new_transaction, on_reattachment},
+    event::{
+        on_balance_change, on_confirmation_state_change, on_new_transaction, on_reattachment,
+        remove_balance_change_listener, remove_confirmation_state_change_listener, remove_new_transaction_listener,
+        remove_reattachment_listener,
+    },
+    message::Transfer,
     signing::SignerType,
 };
+use log::LevelFilter;
 use notify_rust::Notification;
 use tokio::runtime::Runtime;
 
 use std::{
+    collections::HashMap,
     env::var_os,
-    path::PathBuf,
-    sync::{Arc, Mutex},
-    thread::spawn,
+    fs::OpenOptions,
+    io::Write,
+    num::NonZeroU64,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread::{sleep, spawn},
     time::Duration,
 };
 
 mod account;
+mod batch;
+mod config;
+mod output;
+mod units;
 
 const CLI_TEMPLATE: &str = "\
   {before-help}{bin} {version}\n\
@@ -38,13 +63,128 @@ const ACCOUNT_CLI_TEMPLATE: &str = "\
   {all-args}{after-help}\
 ";
 
+// the name the IOTA app reports when it's open on a ledger device
+const LEDGER_APP_NAME: &str = "IOTA";
+
 pub type Result<T> = anyhow::Result<T>;
 
+// headless servers can't show desktop notifications, so events go straight to stdout there
+static NOTIFICATIONS_DISABLED: AtomicBool = AtomicBool::new(false);
+// the only bech32 human-readable part accepted, set by the global `--strict-hrp` flag
+static STRICT_HRP: Mutex<Option<String>> = Mutex::new(None);
+
+// the artificial delay added before network operations, set by the hidden `--simulate-latency` developer flag
+static SIMULATED_LATENCY_MS: AtomicU64 = AtomicU64::new(0);
+
+pub async fn simulate_latency() {
+    let latency = SIMULATED_LATENCY_MS.load(Ordering::Relaxed);
+    if latency > 0 {
+        tokio::time::sleep(Duration::from_millis(latency)).await;
+    }
+}
+
+/// Shows a desktop notification, returning false when it couldn't be shown or notifications are disabled.
+pub fn show_notification(summary: &str, body: &str) -> bool {
+    !NOTIFICATIONS_DISABLED.load(Ordering::Relaxed) && Notification::new().summary(summary).body(body).show().is_ok()
+}
+
+// sets up the logger, which only carries diagnostics while command results are printed to stdout directly: the
+// progress messages and warnings of the CLI are printed as is (only the warnings with `--quiet`), while the logs of the
+// wallet library only show up with `--verbose`; `RUST_LOG` overrides both
+fn init_logger(verbose: bool, quiet: bool) {
+    let cli_level = if quiet { LevelFilter::Warn } else { LevelFilter::Info };
+    let library_level = if verbose { LevelFilter::Debug } else { LevelFilter::Off };
+    env_logger::Builder::new()
+        .filter_level(library_level)
+        .filter_module(module_path!(), cli_level.max(library_level))
+        .parse_env("RUST_LOG")
+        .target(env_logger::Target::Stdout)
+        .format(|buf, record| {
+            if record.target().split("::").next() == Some(module_path!()) {
+                writeln!(buf, "{}", record.args())
+            } else {
+                writeln!(buf, "[{} {}] {}", record.level(), record.target(), record.args())
+            }
+        })
+        .init();
+}
+
 fn print_error<E: ToString>(e: E) {
     println!("ERROR: {}", e.to_string());
 }
 
+pub fn signer_type_name(signer_type: &SignerType) -> &str {
+    match signer_type {
+        SignerType::Stronghold => "stronghold",
+        SignerType::LedgerNano => "ledger-nano",
+        SignerType::LedgerNanoSimulator => "ledger-nano-simulator",
+        SignerType::Custom(name) => name,
+    }
+}
+
+// gets the human-readable part of a bech32 address
+pub fn bech32_hrp(address: &str) -> &str {
+    address.rfind('1').map(|index| &address[..index]).unwrap_or_default()
+}
+
+// parses a bech32 address, rejecting addresses of other networks in `--strict-hrp` mode
+pub fn parse_address(address: &str) -> Result<AddressWrapper> {
+    let parsed =
+        iota_wallet::address::parse(address).map_err(|_| anyhow::anyhow!("Address must be a bech32 string"))?;
+    if let Some(hrp) = &*STRICT_HRP.lock().unwrap() {
+        if bech32_hrp(address) != hrp {
+            return Err(anyhow::anyhow!("Address {} is not on the `{}` network", address, hrp));
+        }
+    }
+    Ok(parsed)
+}
+
+// the default nodes and the bech32 human-readable part of the networks selectable with `new --network`
+fn network_defaults(network: &str) -> Result<(&'static [&'static str], &'static str)> {
+    match network {
+        "mainnet" => Ok((&["https://chrysalis-nodes.iota.org"], "iota")),
+        "testnet" => Ok((&["https://api.lb-0.testnet.chrysalis2.com"], "atoi")),
+        "devnet" => Ok((&["https://api.lb-0.h.chrysalis-devnet.iota.cafe"], "atoi")),
+        _ => Err(anyhow::anyhow!("Network must be one of mainnet, testnet or devnet")),
+    }
+}
+
+// removes duplicated node URLs, treating URLs that only differ by scheme or trailing slash as the same node;
+// the https URL of a node is kept over its other URLs, at the position of the first one
+pub fn dedup_nodes<'a>(nodes: &[&'a str]) -> Vec<&'a str> {
+    let mut positions = HashMap::new();
+    let mut unique: Vec<&'a str> = Vec::new();
+    for node in nodes {
+        let url = node.trim_end_matches('/');
+        let key = url
+            .split_once("://")
+            .map(|(_, host)| host)
+            .unwrap_or(url)
+            .to_lowercase();
+        match positions.get(&key) {
+            None => {
+                positions.insert(key, unique.len());
+                unique.push(url);
+            }
+            Some(&position) => {
+                let is_https = |url: &str| url.to_lowercase().starts_with("https://");
+                if is_https(url) && !is_https(unique[position]) {
+                    log::warn!("Ignoring duplicated node {}", unique[position]);
+                    unique[position] = url;
+                } else {
+                    log::warn!("Ignoring duplicated node {}", node);
+                }
+            }
+        }
+    }
+    unique
+}
+
 fn get_password(manager: &AccountManager) -> String {
+    // the environment variable is meant for automation, where nobody can answer the prompt
+    if let Some(password) = var_os("STRONGHOLD_PASSWORD") {
+        return password.into_string().expect("invalid STRONGHOLD_PASSWORD");
+    }
     let mut prompt = Password::new();
     prompt.with_prompt("What's the stronghold password?");
     if !manager.storage_path().exists() {
@@ -55,23 +195,72 @@ fn get_password(manager: &AccountManager) -> String {
     password
 }
 
-async fn pick_account(accounts: Vec<AccountHandle>) -> Option<usize> {
+// whether the command needs the stronghold, which holds the keys of the stronghold accounts
+async fn needs_stronghold(manager: &AccountManager, matches: &ArgMatches) -> Result<bool> {
+    match matches.subcommand() {
+        Some(("mnemonic", _)) | Some(("backup", _)) => return Ok(true),
+        // the command asks for the current password itself
+        Some(("change-password", _)) => return Ok(false),
+        Some(("new", matches)) => {
+            return Ok(!matches!(
+                matches.value_of("type"),
+                Some("ledger-nano") | Some("ledger-nano-simulator")
+            ))
+        }
+        _ => {}
+    }
+    let accounts = manager.get_accounts().await?;
+    for account in &accounts {
+        if account.signer_type().await == SignerType::Stronghold {
+            return Ok(true);
+        }
+    }
+    Ok(accounts.is_empty())
+}
+
+async fn pick_account(accounts: Vec<AccountHandle>, config: &Config) -> Option<usize> {
     let mut items = Vec::new();
+    let mut ids = Vec::new();
     for account_handle in accounts {
         items.push(account_handle.alias().await);
+        ids.push(account_handle.id().await);
     }
+    // the last used account is preselected, falling back to the default account
+    let default = [&config.last_account, &config.default_account]
+        .iter()
+        .filter_map(|id| id.as_deref())
+        .find_map(|id| ids.iter().position(|item| item == id))
+        .unwrap_or_default();
     Select::with_theme(&ColorfulTheme::default())
         .with_prompt("Select an account to manipulate")
         .items(&items)
-        .default(0)
+        .default(default)
         .interact_on_opt(&Term::stderr())
         .unwrap_or_default()
 }
 
-async fn select_account_command(manager: &AccountManager, matches: &ArgMatches) -> Result<Option<AccountHandle>> {
+async fn select_account_command(
+    manager: &AccountManager,
+    config: &mut Config,
+    matches: &ArgMatches,
+) -> Result<Option<AccountHandle>> {
     if let Some(matches) = matches.subcommand_matches("account") {
-        let alias = matches.value_of("alias").unwrap();
+        let alias = match matches.value_of("alias").or_else(|| config.default_account.as_deref()) {
+            Some(alias) => alias,
+            None => {
+                println!("No account alias given and no default account set");
+                return Ok(None);
+            }
+        };
         if let Ok(account) = manager.get_account(alias).await {
+            if let Some(threshold) = matches.value_of("alert-below") {
+                let threshold = threshold
+                    .parse::<u64>()
+                    .map_err(|_| anyhow::anyhow!("Alert threshold must be a number"))?;
+                config.balance_alerts.insert(account.id().await, threshold);
+                config.save()?;
+                println!("Low balance alert set to {}", threshold);
+            }
             return Ok(Some(account));
         } else {
             println!("Account not found");
@@ -80,28 +269,197 @@ async fn select_account_command(manager: &AccountManager, matches: &ArgMatches)
     Ok(None)
 }
 
+async fn set_default_account_command(
+    manager: &AccountManager,
+    config: &mut Config,
+    matches: &ArgMatches,
+) -> Result<()> {
+    if let Some(matches) = matches.subcommand_matches("set-default-account") {
+        let alias = matches.value_of("alias").unwrap();
+        if let Ok(account) = manager.get_account(alias).await {
+            config.default_account = Some(account.id().await);
+            config.save()?;
+            println!("Default account set to `{}`", alias);
+        } else {
+            println!("Account not found");
+        }
+    }
+    Ok(())
+}
+
 async fn store_mnemonic_command(manager: &mut AccountManager, matches: &ArgMatches) -> Result<bool> {
     if let Some(matches) = matches.subcommand_matches("mnemonic") {
-        let mnemonic = matches.value_of("mnemonic").unwrap().to_string();
+        // reading the phrase from a file keeps it out of the shell history
+        let mnemonic = match (matches.value_of("mnemonic"), matches.value_of("mnemonic-file")) {
+            (Some(mnemonic), None) => mnemonic.to_string(),
+            (None, Some(path)) => std::fs::read_to_string(path)
+                .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path, e))?
+                .split_whitespace()
+                .collect::<Vec<&str>>()
+                .join(" "),
+            (Some(_), Some(_)) => return Err(anyhow::anyhow!("Give either MNEMONIC or --mnemonic-file, not both")),
+            (None, None) => return Err(anyhow::anyhow!("Either MNEMONIC or --mnemonic-file is required")),
+        };
         manager.store_mnemonic(SignerType::Stronghold, Some(mnemonic)).await?;
         return Ok(true);
     }
     Ok(false)
 }
 
+async fn change_password_command(
+    manager: &mut AccountManager,
+    storage_path: &Path,
+    matches: &ArgMatches,
+) -> Result<()> {
+    if matches.subcommand_matches("change-password").is_some() {
+        if !storage_path.join("wallet.stronghold").exists() {
+            return Err(anyhow::anyhow!("There's no stronghold to change the password of"));
+        }
+        let current_password = Password::new()
+            .with_prompt("What's the current stronghold password?")
+            .interact()?;
+        if manager.set_stronghold_password(current_password.clone()).await.is_err() {
+            return Err(anyhow::anyhow!("Wrong password, the password wasn't changed"));
+        }
+        let new_password = Password::new()
+            .with_prompt("What's the new stronghold password?")
+            .with_confirmation("Confirm the new password", "Password mismatch")
+            .interact()?;
+        manager
+            .change_stronghold_password(current_password, new_password)
+            .await?;
+        println!("Stronghold password changed");
+    }
+    Ok(())
+}
+
+async fn verify_mnemonic_command(manager: &mut AccountManager, matches: &ArgMatches) -> Result<()> {
+    if matches.subcommand_matches("verify-mnemonic").is_some() {
+        // the input is hidden so the phrase doesn't end up on the screen
+        let mnemonic = Password::new().with_prompt("Type the mnemonic").interact()?;
+        let mnemonic = mnemonic.split_whitespace().collect::<Vec<&str>>().join(" ");
+        if manager.verify_mnemonic(&mnemonic).is_err() {
+            println!("The mnemonic isn't a valid BIP39 phrase");
+            return Ok(());
+        }
+
+        // the stronghold never reveals the stored mnemonic, so the first address of the first account is compared
+        let mut first_account = None;
+        for account in manager.get_accounts().await? {
+            if account.signer_type().await == SignerType::Stronghold && account.index().await == 0 {
+                first_account = Some(account);
+            }
+        }
+        let account = match first_account {
+            Some(account) => account,
+            None => {
+                println!("The mnemonic is a valid BIP39 phrase, but there's no stronghold account to compare it with");
+                return Ok(());
+            }
+        };
+        let stored_address = account
+            .read()
+            .await
+            .addresses()
+            .iter()
+            .find(|address| *address.key_index() == 0 && !*address.internal())
+            .map(|address| address.address().to_bech32())
+            .ok_or_else(|| anyhow::anyhow!("The first account has no first address to compare with"))?;
+        let seed = Seed::from_bytes(&hex::decode(Client::mnemonic_to_hex_seed(&mnemonic)?)?);
+        let derived_address = GetAddressesBuilder::new(&seed)
+            .with_bech32_hrp(bech32_hrp(&stored_address).to_string())
+            .with_account_index(0)
+            .with_range(0..1)
+            .finish()
+            .await?;
+        if derived_address.first() == Some(&stored_address) {
+            println!("The mnemonic matches the stored one");
+        } else {
+            println!("The mnemonic does NOT match the stored one");
+        }
+    }
+    Ok(())
+}
+
+// reports the state of the ledger device, failing with a hint when it can't sign yet
+async fn check_ledger_status(manager: &AccountManager, is_simulator: bool) -> Result<()> {
+    let status = manager.get_ledger_status(is_simulator).await;
+    let app = status
+        .app
+        .as_ref()
+        .map(|app| format!("{} {}", app.name, app.version))
+        .unwrap_or_else(|| "none".to_string());
+    println!(
+        "Ledger device: connected: {}, locked: {}, open app: {}",
+        status.connected, status.locked, app
+    );
+    if !status.connected {
+        return Err(anyhow::anyhow!(
+            "The ledger device isn't connected, plug it in (or start the simulator) and try again"
+        ));
+    }
+    if status.locked {
+        return Err(anyhow::anyhow!(
+            "The ledger device is locked, unlock it with its PIN and try again"
+        ));
+    }
+    // the dashboard or another app may be open instead
+    match &status.app {
+        Some(app) if app.name == LEDGER_APP_NAME => Ok(()),
+        Some(app) => Err(anyhow::anyhow!(
+            "The `{}` app is open on the ledger device, open the IOTA app instead and try again",
+            app.name
+        )),
+        None => Err(anyhow::anyhow!("Open the IOTA app on the ledger device and try again")),
+    }
+}
+
 async fn new_account_command(manager: &AccountManager, matches: &ArgMatches) -> Result<Option<AccountHandle>> {
     if let Some(matches) = matches.subcommand_matches("new") {
         let nodes: Vec<&str> = matches.values_of("node").map(|v| v.collect()).unwrap_or_default();
+        let mut nodes = dedup_nodes(&nodes);
+        if let Some(network) = matches.value_of("network") {
+            let (default_nodes, hrp) = network_defaults(network)?;
+            if nodes.is_empty() {
+                nodes = default_nodes.to_vec();
+            }
+            // explicitly given nodes must still be on the selected network
+            let mut client = Client::builder().with_node_sync_disabled();
+            for node in &nodes {
+                client = client.with_node(node)?;
+            }
+            let node_hrp = client
+                .finish()
+                .await?
+                .get_bech32_hrp()
+                .await
+                .map_err(|e| anyhow::anyhow!("Could not get the network of the nodes: {}", e))?;
+            if node_hrp != hrp {
+                return Err(anyhow::anyhow!(
+                    "The nodes use the `{}` address prefix but {} addresses use `{}`",
+                    node_hrp,
+                    network,
+                    hrp
+                ));
+            }
+        }
         let signer_type = if let Some(signer_type) = matches.value_of("type") {
             match signer_type {
                 "stronghold" => SignerType::Stronghold,
                 "ledger-nano-simulator" => SignerType::LedgerNanoSimulator,
                 "ledger-nano" => SignerType::LedgerNano,
-                _ => panic!("unexpected account type"),
+                _ => {
+                    return Err(anyhow::anyhow!(
+                        "Account type must be one of stronghold, ledger-nano or ledger-nano-simulator"
+                    ))
+                }
             }
         } else {
             SignerType::Stronghold
         };
+        if signer_type != SignerType::Stronghold {
+            check_ledger_status(manager, signer_type == SignerType::LedgerNanoSimulator).await?;
+        }
 
         let mut builder = manager
             .create_account(
@@ -115,17 +473,160 @@ async fn new_account_command(manager: &AccountManager, matches: &ArgMatches) ->
             builder = builder.alias(alias);
         }
         let account = builder.initialise().await?;
-        println!("Created account `{}`", account.alias().await);
+        let json = matches.is_present("json");
+        if json || output::has_output_file() {
+            let first_address = account
+                .read()
+                .await
+                .addresses()
+                .first()
+                .map(|address| address.address().to_bech32());
+            output::write_json(&serde_json::json!({
+                "account_id": account.id().await,
+                "alias": account.alias().await,
+                "signer_type": signer_type_name(&account.signer_type().await),
+                "first_address": first_address,
+            }))?;
+        }
+        if !json {
+            println!("Created account `{}`", account.alias().await);
+        }
         Ok(Some(account))
     } else {
         Ok(None)
     }
 }
 
+// `completions` command; only the main commands of cli.yml are covered, the account prompt commands of account-cli.yml
+// are read by the interactive prompt, where shell completion doesn't apply
+fn completions_command(matches: &ArgMatches) -> bool {
+    if let Some(matches) = matches.subcommand_matches("completions") {
+        let yaml = load_yaml!("cli.yml");
+        let mut app = App::from(yaml);
+        let mut stdout = std::io::stdout();
+        match matches.value_of("shell").unwrap() {
+            "bash" => generate::<Bash, _>(&mut app, "wallet", &mut stdout),
+            "zsh" => generate::<Zsh, _>(&mut app, "wallet", &mut stdout),
+            _ => generate::<Fish, _>(&mut app, "wallet", &mut stdout),
+        }
+        true
+    } else {
+        false
+    }
+}
+
+// `check-batch` command, exiting with a non-zero code when the file has problems so scripts can lint batch files
+fn check_batch_command(matches: &ArgMatches) -> Result<bool> {
+    if let Some(matches) = matches.subcommand_matches("check-batch") {
+        let path = matches.value_of("path").unwrap();
+        let content = std::fs::read_to_string(path).map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path, e))?;
+        let (outputs, problems) = batch::parse(&content, matches.value_of("network"));
+        if problems.is_empty() {
+            println!("{} outputs parsed, no problems found", outputs.len());
+        } else {
+            for problem in &problems {
+                println!("{}", problem);
+            }
+            print_error(format!("{} problems found in {}", problems.len(), path));
+            std::process::exit(1);
+        }
+        Ok(true)
+    } else {
+        Ok(false)
+    }
+}
+
+async fn move_command(manager: &AccountManager, matches: &ArgMatches) -> Result<()> {
+    if let Some(matches) = matches.subcommand_matches("move") {
+        let from = manager
+            .get_account(matches.value_of("from").unwrap())
+            .await
+            .map_err(|_| anyhow::anyhow!("Source account not found"))?;
+        let to = manager
+            .get_account(matches.value_of("to").unwrap())
+            .await
+            .map_err(|_| anyhow::anyhow!("Destination account not found"))?;
+        let amount = NonZeroU64::new(units::parse_amount(matches.value_of("amount").unwrap())?)
+            .ok_or_else(|| anyhow::anyhow!("Amount must be positive"))?;
+        let available = from.read().await.balance().await?.available;
+        if available < amount.get() {
+            return Err(anyhow::anyhow!(
+                "Insufficient funds on `{}`: {} available",
+                from.alias().await,
+                units::format_amount(available)
+            ));
+        }
+
+        let address = to.generate_address().await?;
+        println!("Destination address: {}", address.address().to_bech32());
+        let message = from
+            .transfer(Transfer::builder(address.address().clone(), amount, None).finish())
+            .await?;
+        account::print_message(&message);
+    }
+    Ok(())
+}
+
+async fn list_accounts_command(manager: &AccountManager, matches: &ArgMatches) -> Result<()> {
+    if let Some(matches) = matches.subcommand_matches("list") {
+        let mut accounts = Vec::new();
+        for account in manager.get_accounts().await? {
+            let total = account.read().await.balance().await?.total;
+            accounts.push((account.alias().await, account.id().await, total));
+        }
+        if matches.is_present("json") || output::has_output_file() {
+            let accounts: Vec<_> = accounts
+                .iter()
+                .map(|(alias, id, total)| serde_json::json!({ "alias": alias, "id": id, "total_balance": total }))
+                .collect();
+            output::write_json(&serde_json::json!(accounts))?;
+        } else if accounts.is_empty() {
+            println!("No accounts found");
+        } else {
+            println!("{:<20} {:<70} {:>16}", "ALIAS", "ID", "BALANCE");
+            for (alias, id, total) in accounts {
+                println!("{:<20} {:<70} {:>16}", alias, id, units::format_amount(total));
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn rename_account_command(manager: &AccountManager, matches: &ArgMatches) -> Result<()> {
+    if let Some(matches) = matches.subcommand_matches("rename") {
+        let alias = matches.value_of("alias").unwrap();
+        let new_alias = matches.value_of("new-alias").unwrap();
+        let account = manager
+            .get_account(alias)
+            .await
+            .map_err(|_| anyhow::anyhow!("Account `{}` not found", alias))?;
+        let previous_alias = account.alias().await;
+        account.set_alias(new_alias).await?;
+        println!("Account `{}` renamed to `{}`", previous_alias, new_alias);
+    }
+    Ok(())
+}
+
 async fn delete_account_command(manager: &AccountManager, matches: &ArgMatches) -> Result<()> {
     if let Some(matches) = matches.subcommand_matches("delete") {
         let account_alias = matches.value_of("alias").unwrap();
         if let Ok(account) = manager.get_account(account_alias).await {
+            if !matches.is_present("force") {
+                let total = account.read().await.balance().await?.total;
+                let prompt = if total > 0 {
+                    format!(
+                        "WARNING: account `{}` still holds {}, its funds will be lost without the mnemonic. Delete it?",
+                        account.alias().await,
+                        units::format_amount(total)
+                    )
+                } else {
+                    format!("Delete account `{}`?", account.alias().await)
+                };
+                if !Confirm::new().with_prompt(prompt).default(false).interact()? {
+                    println!("Account not removed");
+                    return Ok(());
+                }
+            }
             manager.remove_account(&account.id().await).await?;
             println!("Account removed");
         } else {
@@ -135,17 +636,123 @@ async fn delete_account_command(manager: &AccountManager, matches: &ArgMatches)
     Ok(())
 }
 
+async fn watch_command(matches: &ArgMatches) -> Result<()> {
+    if matches.subcommand_matches("watch").is_some() {
+        let balance_listener = on_balance_change(|event| {
+            println!(
+                "[{}] BALANCE {:+} on {} ({})",
+                Local::now().format("%H:%M:%S"),
+                event.balance_change.received as i64 - event.balance_change.spent as i64,
+                event.address.to_bech32(),
+                event.account_id
+            );
+        })
+        .await;
+        let new_transaction_listener = on_new_transaction(|event| {
+            println!(
+                "[{}] NEW {} ({})",
+                Local::now().format("%H:%M:%S"),
+                event.message.id(),
+                event.account_id
+            );
+        })
+        .await;
+        let confirmation_listener = on_confirmation_state_change(|event| {
+            println!(
+                "[{}] {} {} ({})",
+                Local::now().format("%H:%M:%S"),
+                if event.confirmed { "CONFIRMED" } else { "FAILED" },
+                event.message.id(),
+                event.account_id
+            );
+        })
+        .await;
+        let reattachment_listener = on_reattachment(|event| {
+            println!(
+                "[{}] REATTACHED {} ({})",
+                Local::now().format("%H:%M:%S"),
+                event.message.id(),
+                event.account_id
+            );
+        })
+        .await;
+
+        log::info!("Watching the events of every account, press Ctrl-C to stop");
+        tokio::signal::ctrl_c().await?;
+        remove_balance_change_listener(&balance_listener).await;
+        remove_new_transaction_listener(&new_transaction_listener).await;
+        remove_confirmation_state_change_listener(&confirmation_listener).await;
+        remove_reattachment_listener(&reattachment_listener).await;
+    }
+    Ok(())
+}
+
 async fn sync_accounts_command(manager: &AccountManager, matches: &ArgMatches) -> Result<()> {
     if matches.subcommand_matches("sync").is_some() {
-        let synced = manager.sync_accounts()?.execute().await?;
-        println!("Synchronized {} accounts", synced.len());
+        // accounts are synced one by one so a failing account shows up and doesn't stop the others
+        let accounts = manager.get_accounts().await?;
+        let mut failed = 0;
+        for account in &accounts {
+            let alias = account.alias().await;
+            log::info!("Syncing `{}`...", alias);
+            match account.sync().await.execute().await {
+                Ok(synced) => println!(
+                    "--- {} addresses and {} messages found",
+                    synced.addresses().len(),
+                    synced.messages().len()
+                ),
+                Err(e) => {
+                    failed += 1;
+                    println!("--- Failed to sync `{}`: {}", alias, e);
+                }
+            }
+        }
+        println!(
+            "Synchronized {} of {} accounts",
+            accounts.len() - failed,
+            accounts.len()
+        );
+    }
+    Ok(())
+}
+
+// backups to a directory get a timestamped file name so they never overwrite each other
+fn backup_destination(path: &str) -> PathBuf {
+    let path = PathBuf::from(path);
+    if path.is_dir() {
+        // the basic ISO-8601 format has no colons, which some file systems reject
+        path.join(format!(
+            "wallet-backup-{}.stronghold",
+            Local::now().format("%Y%m%dT%H%M%S")
+        ))
+    } else {
+        path
+    }
+}
+
+// checks that the backup can be written, so a mistyped path fails before any password is asked
+fn check_backup_destination(path: &str) -> Result<()> {
+    let destination = backup_destination(path);
+    let directory = match destination.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let metadata =
+        std::fs::metadata(directory).map_err(|_| anyhow::anyhow!("Backup directory {:?} doesn't exist", directory))?;
+    if !metadata.is_dir() {
+        return Err(anyhow::anyhow!("{:?} is not a directory", directory));
     }
+    // the permission bits don't tell whether the current user can write there, so a file is actually created
+    let probe = directory.join(format!(".wallet-backup-probe-{}", std::process::id()));
+    std::fs::File::create(&probe)
+        .map_err(|e| anyhow::anyhow!("Backup directory {:?} is not writable: {}", directory, e))?;
+    let _ = std::fs::remove_file(&probe);
     Ok(())
 }
 
 async fn backup_command(manager: &AccountManager, matches: &ArgMatches) -> Result<()> {
     if let Some(matches) = matches.subcommand_matches("backup") {
-        let destination = matches.value_of("path").unwrap();
+        let destination = backup_destination(matches.value_of("path").unwrap());
         let password = get_password(manager);
         let full_path = manager.backup(destination, password).await?;
         println!("Backup stored at {:?}", full_path);
@@ -163,13 +770,24 @@ async fn import_command(manager: &mut AccountManager, matches: &ArgMatches) -> R
     Ok(())
 }
 
+// renders the notification template configured for the account, if any
+fn custom_notification(storage_path: &Path, account_id: &str, values: &[(&str, &str)]) -> Option<(String, String)> {
+    Config::load(storage_path)
+        .ok()?
+        .notifications
+        .get(account_id)
+        .map(|template| template.render(values))
+}
+
 macro_rules! message_listener {
-    ($listen: ident, $accounts: ident, $runtime: ident, $message_prefix: expr) => {
+    ($listen: ident, $accounts: ident, $runtime: ident, $storage_path: ident, $message_prefix: expr) => {
         let accounts_ = $accounts.clone();
         let runtime_ = $runtime.clone();
+        let storage_path_ = $storage_path.clone();
         $listen(move |event| {
             let accounts = accounts_.clone();
             let runtime_ = runtime_.clone();
+            let storage_path = storage_path_.clone();
             let message = event.message.clone();
             let account_id = event.account_id.clone();
             spawn(move || {
@@ -180,25 +798,27 @@ macro_rules! message_listener {
                         .get(&account_id)
                         .expect("account not found")
                         .clone();
-                    match Notification::new()
-                        .summary("CLI Wallet")
-                        .body(&format!(
-                            "{}: {} on `{}`",
-                            $message_prefix,
-                            message.id().to_string(),
-                            account.read().await.alias()
-                        ))
-                        .show()
-                    {
-                        Ok(_) => {}
-                        Err(_) => {
-                            println!(
-                                "{}: {} on `{}`",
-                                $message_prefix,
-                                message.id().to_string(),
-                                account.read().await.alias()
-                            );
-                        }
+                    let alias = account.read().await.alias().clone();
+                    let message_id = message.id().to_string();
+                    let amount = account::message_value(&message).unwrap_or_default().to_string();
+                    let (summary, body) = custom_notification(
+                        &storage_path,
+                        &account_id,
+                        &[
+                            ("event", $message_prefix),
+                            ("alias", &alias),
+                            ("message_id", &message_id),
+                            ("amount", &amount),
+                        ],
+                    )
+                    .unwrap_or_else(|| {
+                        (
+                            "CLI Wallet".to_string(),
+                            format!("{}: {} on `{}`", $message_prefix, message_id, alias),
+                        )
+                    });
+                    if !show_notification(&summary, &body) {
+                        println!("{}", body);
                     }
                 });
             });
@@ -207,30 +827,228 @@ macro_rules! message_listener {
     };
 }
 
+// appends an event as a JSON line to the event log file
+fn log_event(path: &Path, account_id: &str, event: &str, mut fields: serde_json::Value) {
+    fields["timestamp"] = Local::now().to_rfc3339().into();
+    fields["account_id"] = account_id.into();
+    fields["event"] = event.into();
+    let result = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| writeln!(file, "{}", fields));
+    if let Err(e) = result {
+        print_error(format!("Could not write to the event log {:?}: {}", path, e));
+    }
+}
+
+// records every wallet event to the file set in `WALLET_EVENT_LOG`, independently of the notifications
+async fn register_event_log(path: PathBuf) {
+    let path_ = path.clone();
+    on_balance_change(move |event| {
+        log_event(
+            &path_,
+            &event.account_id,
+            "balance_change",
+            serde_json::json!({
+                "address": event.address.to_bech32(),
+                "received": event.balance_change.received,
+                "spent": event.balance_change.spent,
+            }),
+        );
+    })
+    .await;
+    let path_ = path.clone();
+    on_new_transaction(move |event| {
+        log_event(
+            &path_,
+            &event.account_id,
+            "new_transaction",
+            serde_json::json!({
+                "message_id": event.message.id().to_string(),
+                "value": account::message_value(&event.message),
+            }),
+        );
+    })
+    .await;
+    let path_ = path.clone();
+    on_confirmation_state_change(move |event| {
+        log_event(
+            &path_,
+            &event.account_id,
+            "confirmation_state_change",
+            serde_json::json!({
+                "message_id": event.message.id().to_string(),
+                "confirmed": event.confirmed,
+                "value": account::message_value(&event.message),
+            }),
+        );
+    })
+    .await;
+    on_reattachment(move |event| {
+        log_event(
+            &path,
+            &event.account_id,
+            "reattachment",
+            serde_json::json!({
+                "message_id": event.message.id().to_string(),
+                "reattached_message_id": event.reattached_message_id.to_string(),
+                "value": account::message_value(&event.message),
+            }),
+        );
+    })
+    .await;
+}
+
+// warns when the storage path can be read by other users of the system, without blocking
+#[cfg(unix)]
+fn warn_if_shared_storage(storage_path: &Path) {
+    use std::os::unix::fs::PermissionsExt;
+
+    // a storage that doesn't exist yet will be created inside its parent directory; either way other users must not
+    // be able to write there, while the usual 0755 directories are fine since the stronghold is encrypted
+    let path = if storage_path.exists() {
+        storage_path
+    } else {
+        match storage_path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            Some(parent) => parent,
+            None => return,
+        }
+    };
+    if let Ok(metadata) = std::fs::metadata(path) {
+        if metadata.permissions().mode() & 0o022 != 0 {
+            log::warn!(
+                "WARNING: other users of this system can write to {:?}, where the wallet storage lives, \
+                consider using --db-path or WALLET_DATABASE_PATH to select a private location",
+                path
+            );
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn warn_if_shared_storage(_storage_path: &Path) {}
+
 async fn run() -> Result<()> {
     // ignore stronghold password clear
     iota_wallet::set_stronghold_password_clear_interval(Duration::from_millis(0)).await;
 
-    let storage_path = var_os("WALLET_DATABASE_PATH")
-        .map(|os_str| os_str.into_string().expect("invalid WALLET_DATABASE_PATH"))
-        .unwrap_or_else(|| "./wallet-cli-database".to_string());
+    let yaml = load_yaml!("cli.yml");
+    let matches = App::from(yaml)
+        .help_template(CLI_TEMPLATE)
+        .setting(AppSettings::ColoredHelp)
+        .get_matches();
+
+    // completions don't need the wallet storage
+    if completions_command(&matches) {
+        return Ok(());
+    }
+    init_logger(matches.is_present("verbose"), matches.is_present("quiet"));
+    *STRICT_HRP.lock().unwrap() = matches.value_of("strict-hrp").map(|hrp| hrp.to_string());
+
+    // batch files are checked without opening the wallet storage
+    if check_batch_command(&matches)? {
+        return Ok(());
+    }
+
+    // the `--db-path` flag takes precedence over the environment variable
+    let storage_path = match matches.value_of("db-path") {
+        Some(path) => path.to_string(),
+        None => var_os("WALLET_DATABASE_PATH")
+            .map(|os_str| os_str.into_string().expect("invalid WALLET_DATABASE_PATH"))
+            .unwrap_or_else(|| "./wallet-cli-database".to_string()),
+    };
+    warn_if_shared_storage(Path::new(&storage_path));
     let mut manager = AccountManager::builder()
         .with_storage(&storage_path, None)?
         .finish()
         .await?;
 
+    NOTIFICATIONS_DISABLED.store(
+        matches.is_present("no-notifications") || var_os("WALLET_DISABLE_NOTIFICATIONS").is_some(),
+        Ordering::Relaxed,
+    );
+    if let Some(latency) = matches.value_of("simulate-latency") {
+        // never let the developer flag slow down release builds by accident
+        if cfg!(debug_assertions) || var_os("WALLET_DEVELOPER_MODE").is_some() {
+            let latency = latency
+                .parse::<u64>()
+                .map_err(|_| anyhow::anyhow!("Simulated latency must be a number of milliseconds"))?;
+            SIMULATED_LATENCY_MS.store(latency, Ordering::Relaxed);
+        } else {
+            log::warn!("Ignoring --simulate-latency, set WALLET_DEVELOPER_MODE to enable it in release builds");
+        }
+    }
+    if let Some(path) = matches.value_of("output") {
+        output::set_output_file(PathBuf::from(path));
+    }
+    output::set_pretty(matches.is_present("json-pretty"));
+    units::set_raw(matches.is_present("raw"));
+
+    // balance changes are coalesced into a single notification per window in summary mode
+    let summary_window = if matches.is_present("summary") {
+        let seconds = match matches.value_of("summary-window") {
+            Some(seconds) => seconds
+                .parse::<u64>()
+                .map_err(|_| anyhow::anyhow!("Summary window must be a number of seconds"))?,
+            None => 5,
+        };
+        Some(Duration::from_secs(seconds))
+    } else {
+        None
+    };
+
     let runtime = Runtime::new().expect("Failed to create async runtime");
     let runtime = Arc::new(Mutex::new(runtime));
     let accounts = manager.accounts().clone();
     let accounts_ = accounts.clone();
     let runtime_ = runtime.clone();
+    let storage_path_ = PathBuf::from(&storage_path);
+    let pending_summaries: Arc<Mutex<HashMap<String, (usize, i128)>>> = Default::default();
     on_balance_change(move |event| {
         let accounts = accounts_.clone();
         let runtime_ = runtime_.clone();
+        let config_storage_path = storage_path_.clone();
+        let pending_summaries = pending_summaries.clone();
         let account_id = event.account_id.clone();
         let balance_change = event.balance_change;
         let address = event.address.to_bech32();
         spawn(move || {
+            let balance_message = match summary_window {
+                Some(window) => {
+                    // the first event of the window reports the whole window once it elapsed
+                    let first = {
+                        let mut pending = pending_summaries.lock().unwrap();
+                        let (count, net) = pending.entry(account_id.clone()).or_insert((0, 0));
+                        *count += 1;
+                        *net += balance_change.received as i128 - balance_change.spent as i128;
+                        *count == 1
+                    };
+                    if first {
+                        sleep(window);
+                        let (count, net) = pending_summaries
+                            .lock()
+                            .unwrap()
+                            .remove(&account_id)
+                            .unwrap_or_default();
+                        Some((
+                            format!("{} balance changes, net {}", count, units::format_signed_amount(net)),
+                            net.to_string(),
+                        ))
+                    } else {
+                        None
+                    }
+                }
+                None => {
+                    let balance_message = if balance_change.spent > 0 {
+                        format!("{} spent on address {}", balance_change.spent, address)
+                    } else {
+                        format!("{} received on address {}", balance_change.received, address)
+                    };
+                    let amount = balance_change.received as i128 - balance_change.spent as i128;
+                    Some((balance_message, amount.to_string()))
+                }
+            };
             runtime_.lock().unwrap().block_on(async move {
                 let account = accounts
                     .read()
@@ -238,19 +1056,40 @@ async fn run() -> Result<()> {
                     .get(&account_id)
                     .expect("account not found")
                     .clone();
-                let balance_message = if balance_change.spent > 0 {
-                    format!("{} spent on address {}", balance_change.spent, address)
-                } else {
-                    format!("{} received on address {}", balance_change.received, address)
-                };
-                match Notification::new()
-                    .summary("CLI Wallet")
-                    .body(&format!("{} on `{}`", balance_message, account.read().await.alias()))
-                    .show()
-                {
-                    Ok(_) => {}
-                    Err(_) => {
-                        println!("[BALANCE] {} on `{}`", balance_message, account.read().await.alias());
+                if let Some((balance_message, amount)) = balance_message {
+                    let alias = account.read().await.alias().clone();
+                    let (summary, body) = custom_notification(
+                        &config_storage_path,
+                        &account_id,
+                        &[
+                            ("event", "Balance change"),
+                            ("alias", &alias),
+                            ("amount", &amount),
+                            ("address", &address),
+                        ],
+                    )
+                    .unwrap_or_else(|| ("CLI Wallet".to_string(), format!("{} on `{}`", balance_message, alias)));
+                    if !show_notification(&summary, &body) {
+                        println!("[BALANCE] {}", body);
+                    }
+                }
+
+                if balance_change.spent > 0 {
+                    let account = account.read().await;
+                    let threshold = Config::load(&config_storage_path)
+                        .ok()
+                        .and_then(|config| config.balance_alerts.get(account.id()).copied());
+                    if let (Some(threshold), Ok(balance)) = (threshold, account.balance().await) {
+                        if balance.available < threshold {
+                            let alert = format!(
+                                "available balance {} is below {} on `{}`",
+                                balance.available,
+                                threshold,
+                                account.alias()
+                            );
+                            log::warn!("WARNING: {}", alert);
+                            show_notification("CLI Wallet - Low balance", &alert);
+                        }
                     }
                 }
             });
@@ -258,33 +1097,83 @@ async fn run() -> Result<()> {
     })
     .await;
 
-    message_listener!(on_new_transaction, accounts, runtime, "New transaction");
-    message_listener!(on_confirmation_state_change, accounts, runtime, "Transaction confirmed");
-    message_listener!(on_reattachment, accounts, runtime, "Transaction reattached");
+    let listener_storage_path = PathBuf::from(&storage_path);
+    message_listener!(
+        on_new_transaction,
+        accounts,
+        runtime,
+        listener_storage_path,
+        "New transaction"
+    );
+    message_listener!(
+        on_confirmation_state_change,
+        accounts,
+        runtime,
+        listener_storage_path,
+        "Transaction confirmed"
+    );
+    message_listener!(
+        on_reattachment,
+        accounts,
+        runtime,
+        listener_storage_path,
+        "Transaction reattached"
+    );
+    if let Some(path) = var_os("WALLET_EVENT_LOG") {
+        register_event_log(PathBuf::from(path)).await;
+    }
+
+    // a first run without arguments only gets the welcome text, before any password prompt or mnemonic generation
+    if std::env::args().len() == 1 && manager.get_accounts().await?.is_empty() {
+        println!("Welcome to the wallet CLI! There are no accounts yet, create your first one with:");
+        println!("    $ wallet new --node http://node.url:port --alias ALIAS");
+        println!("Run `wallet help` to see every command.");
+        return Ok(());
+    }
 
     let is_importing = std::env::args().any(|arg| arg == *"import");
 
-    if !is_importing {
+    // ledger accounts keep their keys on the device and the account storage isn't encrypted with the stronghold
+    // password, so it's only asked for when the stronghold is actually used
+    let uses_stronghold = needs_stronghold(&manager, &matches).await?;
+
+    match matches.subcommand() {
+        Some(("backup", matches)) => check_backup_destination(matches.value_of("path").unwrap())?,
+        Some(("new", matches)) => {
+            if let Some(destination) = matches.value_of("auto-backup") {
+                check_backup_destination(destination)?;
+            }
+        }
+        _ => {}
+    }
+
+    // kept for the commands that need it again, like `new --auto-backup`
+    let mut session_password = None;
+    if !is_importing && uses_stronghold {
         loop {
             let password = get_password(&manager);
-            if manager.set_stronghold_password(password).await.is_ok() {
+            if manager.set_stronghold_password(password.clone()).await.is_ok() {
+                session_password = Some(password);
                 break;
             }
+            // retrying would only read the same password again
+            if var_os("STRONGHOLD_PASSWORD").is_some() {
+                return Err(anyhow::anyhow!("Wrong password in STRONGHOLD_PASSWORD"));
+            }
             println!("Wrong password. Try again.");
         }
     }
 
-    let yaml = load_yaml!("cli.yml");
-    let matches = App::from(yaml)
-        .help_template(CLI_TEMPLATE)
-        .setting(AppSettings::ColoredHelp)
-        .setting(AppSettings::ArgRequiredElseHelp)
-        .get_matches();
+    let mut config = Config::load(Path::new(&storage_path))?;
 
     let set_mnemonic = store_mnemonic_command(&mut manager, &matches).await?;
 
     // on first run, we generate a random mnemonic and store it
-    if !(is_importing || PathBuf::from(storage_path).join("wallet.stronghold").exists() || set_mnemonic) {
+    if !(is_importing
+        || !uses_stronghold
+        || Path::new(&storage_path).join("wallet.stronghold").exists()
+        || set_mnemonic)
+    {
         manager.store_mnemonic(SignerType::Stronghold, None).await?;
     }
 
@@ -294,38 +1183,68 @@ async fn run() -> Result<()> {
         .setting(AppSettings::DisableVersion)
         .setting(AppSettings::NoBinaryName);
 
+    if matches.is_present("last") {
+        let id = config
+            .last_account
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("No account has been used yet"))?;
+        let account = manager
+            .get_account(id.as_str())
+            .await
+            .map_err(|_| anyhow::anyhow!("The last used account no longer exists"))?;
+        account::account_prompt(&account_cli, account, &mut config).await;
+        return Ok(());
+    }
+
     if std::env::args().len() == 1 {
         let accounts = manager.get_accounts().await?;
         match accounts.len() {
-            0 => {}
             1 => {
-                account::account_prompt(&account_cli, accounts.first().unwrap().clone()).await;
+                account::account_prompt(&account_cli, accounts.first().unwrap().clone(), &mut config).await;
                 return Ok(());
             }
             _ => {
-                while let Some(index) = pick_account(accounts.clone()).await {
-                    account::account_prompt(&account_cli, accounts[index].clone()).await;
+                while let Some(index) = pick_account(accounts.clone(), &config).await {
+                    account::account_prompt(&account_cli, accounts[index].clone(), &mut config).await;
                 }
             }
         }
     }
 
-    match select_account_command(&manager, &matches).await {
+    match select_account_command(&manager, &mut config, &matches).await {
         Ok(Some(account)) => {
-            account::account_prompt(&account_cli, account).await;
+            account::account_prompt(&account_cli, account, &mut config).await;
         }
         Ok(None) => {}
         Err(e) => return Err(e),
     };
     match new_account_command(&manager, &matches).await {
         Ok(Some(new_account_handle)) => {
-            account::account_prompt(&account_cli, new_account_handle).await;
+            let new_matches = matches.subcommand_matches("new");
+            if let Some(destination) = new_matches.and_then(|matches| matches.value_of("auto-backup")) {
+                let password = session_password.take().unwrap_or_else(|| get_password(&manager));
+                let full_path = manager.backup(backup_destination(destination), password).await?;
+                println!("Backup stored at {:?}", full_path);
+            }
+            let json = new_matches
+                .map(|matches| matches.is_present("json"))
+                .unwrap_or_default();
+            if !json {
+                account::account_prompt(&account_cli, new_account_handle, &mut config).await;
+            }
         }
         Ok(None) => {}
         Err(e) => return Err(e),
     };
+    set_default_account_command(&manager, &mut config, &matches).await?;
+    move_command(&manager, &matches).await?;
+    list_accounts_command(&manager, &matches).await?;
+    rename_account_command(&manager, &matches).await?;
     delete_account_command(&manager, &matches).await?;
     sync_accounts_command(&manager, &matches).await?;
+    change_password_command(&mut manager, Path::new(&storage_path), &matches).await?;
+    verify_mnemonic_command(&mut manager, &matches).await?;
+    watch_command(&matches).await?;
     backup_command(&manager, &matches).await?;
     import_command(&mut manager, &matches).await?;
 
@@ -0,0 +1,56 @@
+// Copyright 2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::Result;
+use serde_json::Value;
+
+use std::{
+    fs,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+// the file structured results are written to, set by the global `--output` flag
+static OUTPUT_FILE: Mutex<Option<PathBuf>> = Mutex::new(None);
+// whether structured results are indented, set by the global `--json-pretty` flag
+static PRETTY: AtomicBool = AtomicBool::new(false);
+
+/// Redirects structured command results to the given file.
+pub fn set_output_file(path: PathBuf) {
+    *OUTPUT_FILE.lock().unwrap() = Some(path);
+}
+
+/// Whether structured command results are redirected to a file.
+pub fn has_output_file() -> bool {
+    OUTPUT_FILE.lock().unwrap().is_some()
+}
+
+/// Indents structured command results instead of writing them on a single line.
+pub fn set_pretty(pretty: bool) {
+    PRETTY.store(pretty, Ordering::Relaxed);
+}
+
+// compact by default so the output stays easy to consume from scripts
+fn format(value: &Value) -> Result<String> {
+    if PRETTY.load(Ordering::Relaxed) {
+        Ok(serde_json::to_string_pretty(value)?)
+    } else {
+        Ok(value.to_string())
+    }
+}
+
+/// Writes a structured command result to the output file, or to stdout if there's none.
+pub fn write_json(value: &Value) -> Result<()> {
+    let content = format(value)?;
+    match &*OUTPUT_FILE.lock().unwrap() {
+        Some(path) => {
+            fs::write(path, content).map_err(|e| anyhow::anyhow!("Failed to write {:?}: {}", path, e))?;
+            println!("Result written to {:?}", path);
+        }
+        None => println!("{}", content),
+    }
+    Ok(())
+}
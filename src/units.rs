@@ -0,0 +1,178 @@
+// Copyright 2021 IOTA Stiftung
+// SPDX-License-Identifier: Apache-2.0
+
+use anyhow::Result;
+
+use std::{
+    convert::TryFrom,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+// the IOTA unit suffixes and the power of ten they stand for
+const UNITS: &[(&str, u32)] = &[("Ki", 3), ("Mi", 6), ("Gi", 9), ("Ti", 12), ("Pi", 15)];
+
+/// Parses an amount of iotas, either a bare integer or a decimal number followed by a unit suffix (`2.5Mi`).
+/// Amounts that would end in a fraction of an iota are rejected instead of truncated.
+pub fn parse_amount(amount: &str) -> Result<u64> {
+    let amount = amount.trim();
+    let (number, exponent) = UNITS
+        .iter()
+        .find_map(|(suffix, exponent)| amount.strip_suffix(suffix).map(|number| (number.trim(), *exponent)))
+        .unwrap_or((amount, 0));
+    let invalid = || anyhow::anyhow!("{} is not a valid amount", amount);
+
+    let (integer, fraction) = number.split_once('.').unwrap_or((number, ""));
+    if (integer.is_empty() && fraction.is_empty())
+        || !integer.chars().chain(fraction.chars()).all(|c| c.is_ascii_digit())
+    {
+        return Err(invalid());
+    }
+    let fraction = fraction.trim_end_matches('0');
+    if fraction.len() > exponent as usize {
+        return Err(anyhow::anyhow!("{} is not a whole number of iotas", amount));
+    }
+
+    let scale = 10u64.pow(exponent);
+    let integer = if integer.is_empty() {
+        0
+    } else {
+        integer.parse::<u64>().map_err(|_| invalid())?
+    };
+    let fraction = if fraction.is_empty() {
+        0
+    } else {
+        fraction.parse::<u64>().map_err(|_| invalid())? * 10u64.pow(exponent - fraction.len() as u32)
+    };
+    integer
+        .checked_mul(scale)
+        .and_then(|integer| integer.checked_add(fraction))
+        .ok_or_else(|| anyhow::anyhow!("{} is too large", amount))
+}
+
+// whether amounts are displayed as plain integers, set by the global `--raw` flag
+static RAW: AtomicBool = AtomicBool::new(false);
+
+/// Displays amounts as plain integers instead of using unit suffixes.
+pub fn set_raw(raw: bool) {
+    RAW.store(raw, Ordering::Relaxed);
+}
+
+/// Formats an amount of iotas with the largest unit suffix it reaches, rounded to two decimals (`2.5 Mi`).
+/// Returns the plain integer when the `--raw` flag is set.
+pub fn format_amount(amount: u64) -> String {
+    if RAW.load(Ordering::Relaxed) {
+        return amount.to_string();
+    }
+    let exponent = UNITS
+        .iter()
+        .rev()
+        .map(|(_, exponent)| *exponent)
+        .find(|exponent| amount >= 10u64.pow(*exponent));
+    match exponent {
+        Some(exponent) => {
+            let suffix = UNITS.iter().find(|(_, e)| *e == exponent).unwrap().0;
+            // integer arithmetic keeps exact values exact
+            let scale = 10u128.pow(exponent);
+            let hundredths = (amount as u128 * 100 + scale / 2) / scale;
+            let decimals = format!("{:02}", hundredths % 100);
+            let decimals = decimals.trim_end_matches('0');
+            if decimals.is_empty() {
+                format!("{} {}", hundredths / 100, suffix)
+            } else {
+                format!("{}.{} {}", hundredths / 100, decimals, suffix)
+            }
+        }
+        None => format!("{} i", amount),
+    }
+}
+
+/// Formats a signed amount of iotas like `format_amount`, always prefixed with its sign (`+12 Mi`, `-3 Ki`).
+pub fn format_signed_amount(amount: i128) -> String {
+    let sign = if amount < 0 { '-' } else { '+' };
+    let magnitude = u64::try_from(amount.unsigned_abs()).unwrap_or(u64::MAX);
+    format!("{}{}", sign, format_amount(magnitude))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_plain_amounts() {
+        assert_eq!(parse_amount("0").unwrap(), 0);
+        assert_eq!(parse_amount("1000000").unwrap(), 1_000_000);
+        assert_eq!(parse_amount(" 42 ").unwrap(), 42);
+        assert_eq!(parse_amount(&u64::MAX.to_string()).unwrap(), u64::MAX);
+    }
+
+    #[test]
+    fn parse_unit_suffixes() {
+        assert_eq!(parse_amount("1Ki").unwrap(), 1_000);
+        assert_eq!(parse_amount("2.5Mi").unwrap(), 2_500_000);
+        assert_eq!(parse_amount("2.5 Mi").unwrap(), 2_500_000);
+        assert_eq!(parse_amount(".5Gi").unwrap(), 500_000_000);
+        assert_eq!(parse_amount("3.Ti").unwrap(), 3_000_000_000_000);
+        assert_eq!(parse_amount("1Pi").unwrap(), 1_000_000_000_000_000);
+        assert_eq!(parse_amount("1.000000Mi").unwrap(), 1_000_000);
+    }
+
+    #[test]
+    fn reject_fractional_iotas() {
+        assert!(parse_amount("1.5").is_err());
+        assert!(parse_amount("1.0001Ki").is_err());
+        assert!(parse_amount("0.0000001Mi").is_err());
+        assert_eq!(
+            parse_amount("1.5000").unwrap_err().to_string(),
+            "1.5000 is not a whole number of iotas"
+        );
+    }
+
+    #[test]
+    fn reject_invalid_amounts() {
+        for amount in &[
+            "", ".", "Mi", "-1", "+1", "1,5Mi", "1e6", "1Xi", "1mi", "1.2.3Mi", "1 000",
+        ] {
+            assert!(parse_amount(amount).is_err(), "{:?} should be rejected", amount);
+        }
+    }
+
+    #[test]
+    fn reject_overflow() {
+        assert!(parse_amount("18446744073709551616").is_err());
+        assert!(parse_amount("18446.744073709551616Pi").is_err());
+        assert_eq!(parse_amount("18446.744073709551615Pi").unwrap(), u64::MAX);
+        assert_eq!(parse_amount("18447Pi").unwrap_err().to_string(), "18447Pi is too large");
+    }
+
+    #[test]
+    fn format_amounts() {
+        assert_eq!(format_amount(0), "0 i");
+        assert_eq!(format_amount(999), "999 i");
+        assert_eq!(format_amount(1_000), "1 Ki");
+        assert_eq!(format_amount(2_500_000), "2.5 Mi");
+        assert_eq!(format_amount(1_230_000_000), "1.23 Gi");
+        assert_eq!(format_amount(1_000_000_000_000_000), "1 Pi");
+    }
+
+    #[test]
+    fn format_rounding_edges() {
+        // rounded half up to two decimals, without trailing zeros
+        assert_eq!(format_amount(1_234), "1.23 Ki");
+        assert_eq!(format_amount(1_235), "1.24 Ki");
+        assert_eq!(format_amount(1_005_000), "1.01 Mi");
+        assert_eq!(format_amount(1_004_999), "1 Mi");
+        // rounding up can reach the next integer but keeps the unit of the amount
+        assert_eq!(format_amount(999_999), "1000 Ki");
+        assert_eq!(format_amount(u64::MAX), "18446.74 Pi");
+    }
+
+    #[test]
+    fn format_signed_amounts() {
+        assert_eq!(format_signed_amount(12_000_000), "+12 Mi");
+        assert_eq!(format_signed_amount(-3_000), "-3 Ki");
+        assert_eq!(format_signed_amount(0), "+0 i");
+        // the net of several balance changes can exceed the range of a single amount
+        assert_eq!(format_signed_amount(2 * u64::MAX as i128), "+18446.74 Pi");
+        assert_eq!(format_signed_amount(-(u64::MAX as i128)), "-18446.74 Pi");
+    }
+}